@@ -1,10 +1,24 @@
 use clap::{Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(name = "claude-vault")]
 #[command(about = "Secure credential management for Claude API", long_about = None)]
 #[command(version)]
 pub struct Cli {
+    /// Keep all claude-vault files in this directory (same as CLAUDE_VAULT_HOME)
+    #[arg(long, global = true, value_name = "DIR")]
+    pub vault_dir: Option<PathBuf>,
+
+    /// Output format of list, show, detect and env, and of errors
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Table)]
+    pub format: OutputFormat,
+
+    /// Profile to use instead of detecting one (same as CLAUDE_VAULT_PROFILE);
+    /// for detect, exec, env, import, switch, login and proxy
+    #[arg(short, long, global = true)]
+    pub profile: Option<String>,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -22,7 +36,15 @@ pub enum Commands {
     },
 
     /// List all profiles
-    List,
+    List {
+        /// Only profiles with this tag (repeatable; all must match)
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+
+        /// Only profiles with this metadata key, or key and value (repeatable)
+        #[arg(long = "meta", value_name = "KEY[=VALUE]")]
+        metadata: Vec<String>,
+    },
 
     /// Show profile details
     Show {
@@ -40,6 +62,58 @@ pub enum Commands {
         yes: bool,
     },
 
+    /// Edit a profile's description, metadata and tags
+    Edit {
+        /// Profile name
+        name: String,
+
+        /// New description (an empty string removes it)
+        #[arg(short, long)]
+        description: Option<String>,
+
+        /// Set a metadata entry; repeatable
+        #[arg(long = "set", value_name = "KEY=VALUE")]
+        set: Vec<String>,
+
+        /// Remove a metadata entry; repeatable
+        #[arg(long = "unset", value_name = "KEY")]
+        unset: Vec<String>,
+
+        /// Add a tag; repeatable
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+
+        /// Remove a tag; repeatable
+        #[arg(long = "untag", value_name = "TAG")]
+        untags: Vec<String>,
+    },
+
+    /// Rename a profile, moving its stored credentials
+    Rename {
+        /// Current profile name
+        old: String,
+
+        /// New profile name
+        new: String,
+
+        /// Also update .claude-profile files naming the profile under this directory (repeatable)
+        #[arg(long = "rewrite", value_name = "ROOT")]
+        rewrite: Vec<PathBuf>,
+    },
+
+    /// Copy a profile's settings and credentials to a new profile
+    Clone {
+        /// Profile to copy
+        source: String,
+
+        /// Name of the new profile
+        dest: String,
+
+        /// Description of the new profile (default: copied from the source)
+        #[arg(short, long)]
+        description: Option<String>,
+    },
+
     /// Set default profile
     Default {
         /// Profile name
@@ -47,7 +121,11 @@ pub enum Commands {
     },
 
     /// Detect profile for current directory
-    Detect,
+    Detect {
+        /// Show each step of the resolution: rules, cache, directories searched, default
+        #[arg(long)]
+        explain: bool,
+    },
 
     /// Initialize project with a profile
     Init {
@@ -57,9 +135,19 @@ pub enum Commands {
 
     /// Execute command with profile credentials
     Exec {
-        /// Profile name (optional, uses detected/default profile)
-        #[arg(short, long)]
-        profile: Option<String>,
+        /// Route API requests through a local credential-injecting proxy
+        /// instead of exporting the credential to the command
+        #[arg(long)]
+        via_proxy: bool,
+
+        /// Give Claude Code a config directory of the profile's own (via
+        /// CLAUDE_CONFIG_DIR) instead of switching its global credentials
+        #[arg(long, conflicts_with = "via_proxy")]
+        isolated: bool,
+
+        /// Refresh OAuth tokens unless they stay valid for at least this many minutes
+        #[arg(long, value_name = "MINUTES")]
+        min_validity: Option<i64>,
 
         /// Command to execute
         #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
@@ -67,11 +155,7 @@ pub enum Commands {
     },
 
     /// Print environment variables for shell integration
-    Env {
-        /// Profile name (optional, uses detected/default profile)
-        #[arg(short, long)]
-        profile: Option<String>,
-    },
+    Env,
 
     /// Generate shell completion scripts
     Completion {
@@ -83,14 +167,180 @@ pub enum Commands {
     Import {
         /// Import type (currently only "oauth" supported)
         import_type: String,
+    },
 
-        /// Profile name (optional, uses "default" if not specified)
-        #[arg(short, long)]
+    /// Switch Claude Code to a profile's account until switched again
+    Switch {
+        /// Profile to switch to (shows the active profile if omitted)
+        #[arg(id = "switch_to", value_name = "PROFILE", conflicts_with = "back")]
         profile: Option<String>,
+
+        /// Switch back to the account used before the last switch
+        #[arg(long)]
+        back: bool,
+
+        /// Profile name for the current Claude Code account if it isn't stored yet
+        #[arg(long, value_name = "NAME")]
+        capture_as: Option<String>,
     },
+
+    /// Restore Claude Code credentials left switched by an interrupted exec
+    Recover,
+
+    /// Log in with OAuth (authorization code + PKCE) and store the tokens
+    Login {
+        /// Authorization endpoint (default from config)
+        #[arg(long)]
+        authorize_url: Option<String>,
+
+        /// Token endpoint (default from config)
+        #[arg(long)]
+        token_url: Option<String>,
+
+        /// OAuth client ID (default from config)
+        #[arg(long)]
+        client_id: Option<String>,
+
+        /// Scope to request; repeat for several (default from config)
+        #[arg(long = "scope", value_name = "SCOPE")]
+        scopes: Vec<String>,
+
+        /// Extra token request parameter as KEY=VALUE; repeatable
+        #[arg(long = "param", value_name = "KEY=VALUE")]
+        params: Vec<String>,
+
+        /// Loopback port for the redirect (default: any free port)
+        #[arg(long, default_value_t = 0)]
+        port: u16,
+
+        /// Print the authorization URL instead of opening a browser
+        #[arg(long)]
+        no_browser: bool,
+    },
+
+    /// Run a local proxy that injects profile credentials into API requests
+    Proxy {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8787")]
+        listen: String,
+
+        /// Allow a non-loopback --listen address, exposing the proxy to the network
+        #[arg(long)]
+        allow_remote: bool,
+
+        /// Upstream API base URL (default: https://api.anthropic.com)
+        #[arg(long)]
+        upstream: Option<String>,
+    },
+
+    /// Manage the background credential agent
+    Agent {
+        #[command(subcommand)]
+        action: AgentAction,
+    },
+
+    /// Manage config.toml
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
+    /// Manage path rules mapping directories to profiles
+    Rule {
+        #[command(subcommand)]
+        action: RuleAction,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    /// Upgrade config.toml written by an older claude-vault (a backup is kept)
+    Migrate {
+        /// Show the changes as a diff without writing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum RuleAction {
+    /// Use a profile for directories matching a glob (e.g. '~/work/**'), or
+    /// with --remote for repositories whose git remote matches (e.g. 'github.com/acme')
+    Add {
+        /// Glob pattern; a match covers everything below it
+        pattern: String,
+
+        /// Profile name
+        profile: String,
+
+        /// Match the repository's git remote (host/path) instead of the directory
+        #[arg(long)]
+        remote: bool,
+    },
+
+    /// List rules in the order they are checked
+    List,
+
+    /// Remove the rule for a pattern
+    Remove {
+        /// Pattern as shown by 'rule list'
+        pattern: String,
+
+        /// Remove a remote rule
+        #[arg(long)]
+        remote: bool,
+    },
+
+    /// Show which rule applies to a path
+    Test {
+        /// Path to check (default: current directory)
+        path: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum AgentAction {
+    /// Start the agent and print shell exports (use with eval)
+    Start {
+        /// Socket path (default: agent.sock in the state directory)
+        #[arg(long)]
+        socket: Option<PathBuf>,
+
+        /// Seconds a cached credential may go unused before it is forgotten
+        #[arg(long)]
+        ttl: Option<u64>,
+
+        /// Run in the foreground instead of detaching
+        #[arg(long)]
+        foreground: bool,
+    },
+
+    /// Stop the running agent
+    Stop,
+
+    /// Lock the agent with a password until unlocked
+    Lock,
+
+    /// Unlock a locked agent
+    Unlock,
+
+    /// List cached credentials (secrets are never shown)
+    List,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable text
+    Table,
+    /// JSON
+    Json,
+    /// YAML
+    Yaml,
 }
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
+// `PowerShell` is the variant clap turns into the `power-shell` value users type
+#[allow(clippy::enum_variant_names)]
 pub enum Shell {
     /// Bash shell
     Bash,
@@ -1,31 +1,118 @@
-use crate::cli::commands::{Cli, Commands, Shell};
-use crate::core::{detect_profile, init_profile, ProfileManager};
+use crate::cli::commands::{
+    AgentAction, Cli, Commands, ConfigAction, OutputFormat, RuleAction, Shell,
+};
+use crate::cli::output;
+use crate::core::detector::{
+    self, detect, explain_for_dir, rewrite_profile_files, CacheLookup, Detection, DetectionSource,
+    TraceStep, PROFILE_ENV,
+};
+use crate::core::store::SecretKind;
+use crate::core::{init_profile, proxy, ProfileManager};
 use crate::error::Result;
+use crate::types::{
+    CredentialType, OAuthOverrides, Profile, ProfileEdit, ProfileFilter, ProjectProfile,
+};
+use crate::utils::parse_key_value;
 use clap::CommandFactory;
 use clap_complete::{generate, shells};
 use dialoguer::{Confirm, Password};
+use serde::Serialize;
+use std::collections::BTreeMap;
 use std::io;
+use std::path::PathBuf;
 use std::process::Command;
 
 pub fn handle_command(cli: Cli) -> Result<()> {
+    let format = cli.format;
+    let pinned = cli.profile.as_deref();
+
+    // The others name their profile as an argument; don't let -p look like it did something
+    if pinned.is_some() && !uses_profile_flag(&cli.command) {
+        return Err(crate::error::Error::ConfigError(
+            "--profile only applies to detect, exec, env, import, switch, login and proxy; \
+             commands such as show and remove take the profile name as an argument"
+                .to_string(),
+        ));
+    }
+
     match cli.command {
         Commands::Add { name, description } => handle_add(name, description),
-        Commands::List => handle_list(),
-        Commands::Show { name } => handle_show(name),
+        Commands::List { tags, metadata } => handle_list(format, tags, metadata),
+        Commands::Show { name } => handle_show(format, name),
         Commands::Remove { name, yes } => handle_remove(name, yes),
+        Commands::Edit {
+            name,
+            description,
+            set,
+            unset,
+            tags,
+            untags,
+        } => handle_edit(name, description, set, unset, tags, untags),
+        Commands::Rename { old, new, rewrite } => handle_rename(old, new, rewrite),
+        Commands::Clone {
+            source,
+            dest,
+            description,
+        } => handle_clone(source, dest, description),
         Commands::Default { name } => handle_default(name),
-        Commands::Detect => handle_detect(),
+        Commands::Detect { explain } => handle_detect(format, pinned, explain),
         Commands::Init { name } => handle_init(name),
-        Commands::Exec { profile, command } => handle_exec(profile, command),
-        Commands::Env { profile } => handle_env(profile),
+        Commands::Exec {
+            via_proxy,
+            isolated,
+            min_validity,
+            command,
+        } => handle_exec(pinned, via_proxy, isolated, min_validity, command),
+        Commands::Env => handle_env(format, pinned),
         Commands::Completion { shell } => handle_completion(shell),
-        Commands::Import {
-            import_type,
+        Commands::Import { import_type } => handle_import(import_type, pinned),
+        Commands::Switch {
             profile,
-        } => handle_import(import_type, profile),
+            back,
+            capture_as,
+        } => handle_switch(
+            profile.or_else(|| detector::pinned(pinned)),
+            back,
+            capture_as,
+        ),
+        Commands::Recover => handle_recover(),
+        Commands::Login {
+            authorize_url,
+            token_url,
+            client_id,
+            scopes,
+            params,
+            port,
+            no_browser,
+        } => {
+            let overrides = oauth_overrides(authorize_url, token_url, client_id, scopes, params)?;
+            handle_login(pinned, overrides, port, no_browser)
+        }
+        Commands::Proxy {
+            listen,
+            allow_remote,
+            upstream,
+        } => handle_proxy(pinned, listen, allow_remote, upstream),
+        Commands::Agent { action } => handle_agent(action),
+        Commands::Config { action } => handle_config(action),
+        Commands::Rule { action } => handle_rule(format, action),
     }
 }
 
+/// Whether `command` resolves a profile, and so honours the global `--profile`
+fn uses_profile_flag(command: &Commands) -> bool {
+    matches!(
+        command,
+        Commands::Detect { .. }
+            | Commands::Exec { .. }
+            | Commands::Env
+            | Commands::Import { .. }
+            | Commands::Switch { .. }
+            | Commands::Login { .. }
+            | Commands::Proxy { .. }
+    )
+}
+
 fn handle_add(name: String, description: Option<String>) -> Result<()> {
     println!("Adding profile '{}'", name);
 
@@ -46,10 +133,28 @@ fn handle_add(name: String, description: Option<String>) -> Result<()> {
     Ok(())
 }
 
-fn handle_list() -> Result<()> {
-    let profiles = ProfileManager::list()?;
+fn handle_list(format: OutputFormat, tags: Vec<String>, metadata: Vec<String>) -> Result<()> {
+    let filter = ProfileFilter {
+        tags,
+        metadata: metadata
+            .into_iter()
+            .map(|arg| match arg.split_once('=') {
+                Some((key, value)) => (key.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            })
+            .collect(),
+    };
+    let profiles = ProfileManager::list_filtered(&filter)?;
+
+    if format != OutputFormat::Table {
+        return output::print(format, &profiles);
+    }
 
     if profiles.is_empty() {
+        if !filter.is_empty() {
+            println!("No profiles match the filter.");
+            return Ok(());
+        }
         println!("No profiles found.");
         println!("Add a profile with: claude-vault add <name>");
         return Ok(());
@@ -58,10 +163,13 @@ fn handle_list() -> Result<()> {
     println!("Profiles:");
     for profile in profiles {
         print!("  • {}", profile.name);
-        if let Some(desc) = profile.description {
+        if let Some(ref desc) = profile.description {
             print!(" - {}", desc);
         }
         println!();
+        if !profile.tags.is_empty() {
+            println!("    Tags: {}", profile.tags.join(", "));
+        }
         if let Some(last_used) = profile.last_used {
             println!("    Last used: {}", last_used.to_rfc3339());
         }
@@ -70,8 +178,115 @@ fn handle_list() -> Result<()> {
     Ok(())
 }
 
-fn handle_show(name: String) -> Result<()> {
+/// Print a profile's tags and metadata, metadata sorted by key
+fn print_labels(profile: &Profile) {
+    if !profile.tags.is_empty() {
+        println!("Tags: {}", profile.tags.join(", "));
+    }
+
+    let metadata: BTreeMap<_, _> = profile.metadata.iter().collect();
+    if !metadata.is_empty() {
+        println!("Metadata:");
+        for (key, value) in metadata {
+            println!("  {} = {}", key, value);
+        }
+    }
+}
+
+fn handle_edit(
+    name: String,
+    description: Option<String>,
+    set: Vec<String>,
+    unset: Vec<String>,
+    tags: Vec<String>,
+    untags: Vec<String>,
+) -> Result<()> {
+    let edit = ProfileEdit {
+        description,
+        set: set
+            .iter()
+            .map(|arg| parse_key_value(arg))
+            .collect::<Result<_>>()?,
+        unset,
+        tags,
+        untags,
+    };
+
+    if edit.is_empty() {
+        return Err(crate::error::Error::ConfigError(
+            "Nothing to change: pass --description, --set, --unset, --tag or --untag".to_string(),
+        ));
+    }
+
+    let profile = ProfileManager::edit(&name, &edit)?;
+
+    println!("✓ Profile '{}' updated", profile.name);
+    if let Some(ref desc) = profile.description {
+        println!("Description: {}", desc);
+    }
+    print_labels(&profile);
+
+    Ok(())
+}
+
+/// `show` output in structured formats: the profile plus what is known about
+/// its secrets, never the secrets themselves
+#[derive(Serialize)]
+struct ProfileDetails<'a> {
+    #[serde(flatten)]
+    profile: &'a Profile,
+    /// None if the credential store could not be checked
+    stored_secrets: Option<Vec<SecretKind>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<ExpiryStatus>,
+}
+
+/// Where an expiring credential stands
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum ExpiryStatus {
+    Expired,
+    /// OAuth token inside its refresh window
+    RefreshDue,
+    ExpiringSoon,
+    Valid,
+}
+
+fn handle_show(format: OutputFormat, name: String) -> Result<()> {
     let profile = ProfileManager::get(&name)?;
+    // A locked keychain or unavailable passphrase shouldn't keep the rest from showing
+    let stored = match crate::core::keychain::list_stored(&profile.name) {
+        Ok(stored) => Some(stored),
+        Err(e) => {
+            eprintln!("⚠️  Warning: Could not check stored secrets: {}", e);
+            None
+        }
+    };
+    let policy = crate::core::config::load()?.expiry;
+
+    let status = profile.expires_at.map(|_| {
+        if profile.is_expired() {
+            ExpiryStatus::Expired
+        } else if profile.credential_type == CredentialType::OAuth && profile.needs_refresh(&policy)
+        {
+            ExpiryStatus::RefreshDue
+        } else if profile.expires_soon(&policy) {
+            ExpiryStatus::ExpiringSoon
+        } else {
+            ExpiryStatus::Valid
+        }
+    });
+
+    if format != OutputFormat::Table {
+        return output::print(
+            format,
+            &ProfileDetails {
+                profile: &profile,
+                stored_secrets: stored,
+                status,
+            },
+        );
+    }
 
     println!("Profile: {}", profile.name);
     println!("Type: {}", profile.credential_type);
@@ -80,6 +295,40 @@ fn handle_show(name: String) -> Result<()> {
         println!("Description: {}", desc);
     }
 
+    print_labels(&profile);
+
+    match stored {
+        Some(ref stored) if !stored.is_empty() => {
+            let kinds: Vec<String> = stored.iter().map(|kind| kind.to_string()).collect();
+            println!("Stored secrets: {}", kinds.join(", "));
+        }
+        Some(_) => {}
+        None => println!("Stored secrets: unknown"),
+    }
+
+    if profile.credential_type == crate::types::CredentialType::OAuth {
+        let oauth = crate::core::config::load()?.oauth_for(&profile);
+        println!("Token endpoint: {}", oauth.token_endpoint);
+        println!("Client ID: {}", oauth.client_id);
+        if !profile.oauth.is_empty() {
+            println!("OAuth settings: overridden for this profile");
+        }
+        if let Some(ref subscription_type) = profile.subscription_type {
+            println!("Subscription: {}", subscription_type);
+        }
+        if let Some(ref scopes) = profile.scopes {
+            println!("Scopes: {}", scopes.join(" "));
+        }
+        if let Some(ref account) = profile.account {
+            if let Some(ref email) = account.email {
+                println!("Account: {}", email);
+            }
+            if let Some(ref organization) = account.organization_name {
+                println!("Organization: {}", organization);
+            }
+        }
+    }
+
     println!("Created: {}", profile.created_at.to_rfc3339());
 
     if let Some(last_used) = profile.last_used {
@@ -89,12 +338,23 @@ fn handle_show(name: String) -> Result<()> {
     if let Some(expires_at) = profile.expires_at {
         println!("Expires: {}", expires_at.to_rfc3339());
 
-        if profile.is_expired() {
-            println!("Status: ⚠️  EXPIRED");
-        } else if profile.expires_soon() {
-            println!("Status: ⚠️  Expires soon (within 24 hours)");
-        } else {
-            println!("Status: ✓ Valid");
+        if profile.credential_type == crate::types::CredentialType::OAuth {
+            println!(
+                "Refresh window: {} minutes before expiry",
+                profile.refresh_window(&policy).num_minutes()
+            );
+        }
+
+        match status {
+            Some(ExpiryStatus::Expired) => println!("Status: ⚠️  EXPIRED"),
+            Some(ExpiryStatus::RefreshDue) => {
+                println!("Status: 🔄 Inside refresh window (refreshed on next use)")
+            }
+            Some(ExpiryStatus::ExpiringSoon) => println!(
+                "Status: ⚠️  Expires soon (within {} hours)",
+                policy.warn_ahead_hours
+            ),
+            _ => println!("Status: ✓ Valid"),
         }
     }
 
@@ -126,19 +386,44 @@ fn handle_remove(name: String, yes: bool) -> Result<()> {
     Ok(())
 }
 
+fn handle_rename(old: String, new: String, rewrite: Vec<PathBuf>) -> Result<()> {
+    ProfileManager::rename(&old, &new)?;
+    println!("✓ Profile '{}' renamed to '{}'", old, new);
+
+    let rewritten = rewrite_profile_files(&rewrite, &old, &new)?;
+    for path in &rewritten {
+        println!("  Updated: {}", path.display());
+    }
+
+    Ok(())
+}
+
+fn handle_clone(source: String, dest: String, description: Option<String>) -> Result<()> {
+    let profile = ProfileManager::clone(&source, &dest, description)?;
+    println!("✓ Profile '{}' created from '{}'", dest, source);
+    println!("  Type: {}", profile.credential_type);
+
+    Ok(())
+}
+
 fn handle_default(name: String) -> Result<()> {
     ProfileManager::set_default(&name)?;
     println!("✓ Default profile set to '{}'", name);
     Ok(())
 }
 
-fn handle_detect() -> Result<()> {
-    match detect_profile() {
-        Ok(profile) => {
-            println!("Detected profile: {}", profile);
+fn handle_detect(format: OutputFormat, profile_opt: Option<&str>, explain: bool) -> Result<()> {
+    if explain {
+        return handle_detect_explain(format, profile_opt);
+    }
+
+    match detect(profile_opt) {
+        Ok(detection) if format != OutputFormat::Table => output::print(format, &detection),
+        Ok(detection) => {
+            print_detection(&detection);
             Ok(())
         }
-        Err(crate::error::Error::NoProfileDetected) => {
+        Err(crate::error::Error::NoProfileDetected) if format == OutputFormat::Table => {
             println!("No profile detected.");
             println!("Suggestions:");
             println!("  - Run 'claude-vault init <profile>' to set up this project");
@@ -149,6 +434,112 @@ fn handle_detect() -> Result<()> {
     }
 }
 
+fn print_detection(detection: &Detection) {
+    println!("Detected profile: {}", detection.profile);
+    match &detection.source {
+        DetectionSource::Flag => println!("  Source: --profile"),
+        DetectionSource::Env => println!("  Source: {}", PROFILE_ENV),
+        DetectionSource::Rule { pattern } => println!("  Source: rule '{}'", pattern),
+        DetectionSource::RemoteRule { pattern, remote } => {
+            println!("  Source: remote rule '{}' ({})", pattern, remote)
+        }
+        DetectionSource::Cache => println!("  Source: cache"),
+        DetectionSource::ProfileFile { path } => println!("  Source: {}", path.display()),
+        DetectionSource::Default => println!("  Source: default profile"),
+    }
+}
+
+#[derive(Serialize)]
+struct DetectExplanation<'a> {
+    dir: PathBuf,
+    steps: Vec<TraceStep>,
+    /// Absent when detection failed
+    detection: Option<&'a Detection>,
+}
+
+fn handle_detect_explain(format: OutputFormat, profile_opt: Option<&str>) -> Result<()> {
+    let dir = std::env::current_dir()?;
+    let (steps, detection) = explain_for_dir(&dir, profile_opt)?;
+
+    if format != OutputFormat::Table {
+        output::print(
+            format,
+            &DetectExplanation {
+                dir,
+                steps,
+                detection: detection.as_ref().ok(),
+            },
+        )?;
+        return detection.map(|_| ());
+    }
+
+    println!("Resolving profile for {}", dir.display());
+    for step in &steps {
+        print_trace_step(step);
+    }
+    println!();
+
+    let detection = detection?;
+    print_detection(&detection);
+    Ok(())
+}
+
+fn print_trace_step(step: &TraceStep) {
+    match step {
+        TraceStep::Flag { profile } => println!("  --profile: {}", profile),
+        TraceStep::Env {
+            variable,
+            profile: Some(profile),
+        } => println!("  {}: {}", variable, profile),
+        TraceStep::Env { variable, .. } => println!("  {}: not set", variable),
+        TraceStep::Rules {
+            checked,
+            matched: Some(rule),
+            on,
+        } => {
+            println!(
+                "  Rules: {} checked, {} → {}",
+                checked, rule.target, rule.profile
+            );
+            match on {
+                Some(crate::core::rules::Matched::Directory { path }) => {
+                    println!("    matched {}", path.display())
+                }
+                Some(crate::core::rules::Matched::Remote { name, url }) => {
+                    println!("    matched remote '{}' ({})", name, url)
+                }
+                None => {}
+            }
+        }
+        TraceStep::Rules { checked, .. } => println!("  Rules: {} checked, no match", checked),
+        TraceStep::Cache { lookup } => match lookup {
+            CacheLookup::Miss => println!("  Cache: miss"),
+            CacheLookup::Hit {
+                profile,
+                age_seconds,
+            } => println!("  Cache: hit, {} (cached {}s ago)", profile, age_seconds),
+            CacheLookup::Stale {
+                profile,
+                age_seconds,
+                reason,
+            } => println!(
+                "  Cache: ignored {} (cached {}s ago): {}",
+                profile, age_seconds, reason
+            ),
+        },
+        TraceStep::Directory { path, found: true } => {
+            println!("  {}: .claude-profile found", path.display())
+        }
+        TraceStep::Directory { path, found: false } => {
+            println!("  {}: no .claude-profile", path.display())
+        }
+        TraceStep::Default {
+            profile: Some(profile),
+        } => println!("  Default profile: {}", profile),
+        TraceStep::Default { profile: None } => println!("  Default profile: not set"),
+    }
+}
+
 fn handle_init(name: String) -> Result<()> {
     let profile_file = init_profile(&name)?;
 
@@ -164,23 +555,76 @@ fn handle_init(name: String) -> Result<()> {
     Ok(())
 }
 
-fn handle_exec(profile_opt: Option<String>, command: Vec<String>) -> Result<()> {
+fn handle_exec(
+    profile_opt: Option<&str>,
+    via_proxy: bool,
+    isolated: bool,
+    min_validity: Option<i64>,
+    command: Vec<String>,
+) -> Result<()> {
     // Resolve profile name
-    let profile_name = resolve_profile(profile_opt)?;
+    let Detection {
+        profile: profile_name,
+        project,
+        ..
+    } = detect(profile_opt)?;
+    let mut project_env = project_env(project.as_ref())?;
+
+    if isolated {
+        if ProfileManager::get(&profile_name)?.credential_type
+            != crate::types::CredentialType::OAuth
+        {
+            return Err(crate::error::Error::ConfigError(format!(
+                "Profile '{}' is not an OAuth profile. --isolated requires OAuth tokens.",
+                profile_name
+            )));
+        }
 
-    // Ensure token is valid (auto-refresh if expired)
-    crate::core::ensure_token_valid(&profile_name)?;
+        // Claude Code may have rotated the refresh token during the last isolated run
+        crate::core::claude_code::sync_isolated_config(&profile_name)?;
+    }
+
+    // Ensure token is valid (auto-refresh if expired or about to expire)
+    let min_validity = min_validity.map(chrono::Duration::minutes);
+    let valid_until = crate::core::ensure_token_valid_for(&profile_name, min_validity)?;
 
     // Get profile to check credential type and expiration
     let profile = ProfileManager::get(&profile_name)?;
+    let policy = crate::core::config::load()?.expiry;
+
+    // Warn if expiring soon
+    if profile.expires_soon(&policy) {
+        eprintln!(
+            "⚠️  Warning: Profile '{}' credentials expire soon (within {} hours)",
+            profile_name, policy.warn_ahead_hours
+        );
+    }
 
-    // Warn if expiring soon (within 24 hours)
-    if profile.expires_soon() {
-        eprintln!("⚠️  Warning: Profile '{}' credentials expire soon (within 24 hours)", profile_name);
+    if let Some(valid_until) = valid_until {
+        if min_validity.is_some() {
+            eprintln!(
+                "✓ Token valid for {} (until {})",
+                crate::utils::format_duration(valid_until - chrono::Utc::now()),
+                valid_until.to_rfc3339()
+            );
+        }
     }
+    let valid_until = valid_until.map(|valid_until| valid_until.to_rfc3339());
+    // With the proxy, the project's base URL is where the proxy forwards to
+    let project_upstream = if via_proxy {
+        project_env.remove("ANTHROPIC_BASE_URL")
+    } else {
+        None
+    };
 
-    // Get credential from keychain based on type
-    let credential = crate::core::keychain::get_by_type(&profile_name, profile.credential_type)?;
+    // Project settings first, so the variables claude-vault sets below win
+    let mut envs: Vec<(&str, &str)> = project_env
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect();
+    if let Some(ref valid_until) = valid_until {
+        envs.push(("CLAUDE_VAULT_TOKEN_EXPIRES_AT", valid_until));
+    }
 
     // Update last_used timestamp
     ProfileManager::update_last_used(&profile_name)?;
@@ -192,40 +636,153 @@ fn handle_exec(profile_opt: Option<String>, command: Vec<String>) -> Result<()>
         ));
     }
 
-    // For OAuth profiles, also switch Claude Code keychain
-    let exit_code = if profile.credential_type == crate::types::CredentialType::OAuth {
-        // Use with_claude_code_profile to automatically backup and restore
-        crate::core::with_claude_code_profile(&profile_name, || {
-            let status = Command::new(&command[0])
-                .args(&command[1..])
-                .env("ANTHROPIC_API_KEY", &credential)
-                .status()
-                .map_err(|e| {
-                    crate::error::Error::ConfigError(format!("Failed to execute command: {}", e))
-                })?;
+    let exit_code = if via_proxy {
+        // Keep the credential out of the child's environment: it only sees
+        // a per-session key the proxy checks before injecting the real one
+        let listener = proxy::bind("127.0.0.1:0", false)?;
+        let session_token = proxy::session_token();
+        let base_url = format!("http://{}", listener.local_addr()?);
+        let upstream = match project_upstream {
+            Some(upstream) => upstream,
+            None => crate::core::config::load()?.proxy.upstream,
+        };
+        let credential = proxy::profile_credential(profile_name.clone(), profile.credential_type);
+
+        let child_token = session_token.clone();
+        std::thread::spawn(move || {
+            proxy::serve(
+                listener,
+                &upstream,
+                session_token,
+                profile.credential_type,
+                credential,
+            )
+        });
 
-            Ok(status.code().unwrap_or(1))
-        })?
-    } else {
-        // For API key profiles, just set environment variable
-        let status = Command::new(&command[0])
-            .args(&command[1..])
-            .env("ANTHROPIC_API_KEY", credential)
-            .status()
-            .map_err(|e| {
-                crate::error::Error::ConfigError(format!("Failed to execute command: {}", e))
-            })?;
+        envs.push(("ANTHROPIC_BASE_URL", &base_url));
+        envs.push(("ANTHROPIC_API_KEY", &child_token));
+        run_command(&command, &envs)?
+    } else if isolated {
+        // Claude Code reads the profile's credentials from its own config
+        // directory, so other terminals are unaffected
+        let shared = crate::core::config::load()?.isolation.shared;
+        let config_dir = crate::core::claude_code::prepare_isolated_config(&profile_name, &shared)?;
+        let config_dir = config_dir.to_string_lossy().into_owned();
+        envs.push((crate::core::claude_code::CLAUDE_CONFIG_DIR_ENV, &config_dir));
+
+        let exit_code = run_command(&command, &envs)?;
+
+        match crate::core::claude_code::sync_isolated_config(&profile_name) {
+            Ok(true) => eprintln!(
+                "🔄 Claude Code refreshed the token for profile '{}'; saved it to the vault",
+                profile_name
+            ),
+            Ok(false) => {}
+            Err(e) => eprintln!(
+                "⚠️  Warning: Failed to save tokens refreshed by Claude Code to profile '{}': {}",
+                profile_name, e
+            ),
+        }
 
-        status.code().unwrap_or(1)
+        exit_code
+    } else {
+        // Get credential from keychain based on type
+        let credential =
+            crate::core::keychain::get_by_type(&profile_name, profile.credential_type)?;
+        envs.push(("ANTHROPIC_API_KEY", &credential));
+
+        if profile.credential_type == crate::types::CredentialType::OAuth {
+            // For OAuth profiles, also switch Claude Code keychain
+            // Use with_claude_code_profile to automatically backup and restore
+            crate::core::with_claude_code_profile(&profile_name, || run_command(&command, &envs))?
+        } else {
+            // For API key profiles, just set environment variable
+            run_command(&command, &envs)?
+        }
     };
 
     // Exit with the same code as the child process
     std::process::exit(exit_code);
 }
 
-fn handle_env(profile_opt: Option<String>) -> Result<()> {
+/// Run a command with extra environment variables, returning its exit code
+fn run_command(command: &[String], envs: &[(&str, &str)]) -> Result<i32> {
+    let status = Command::new(&command[0])
+        .args(&command[1..])
+        .envs(envs.iter().copied())
+        .status()
+        .map_err(|e| crate::error::Error::CommandFailed {
+            command: command[0].clone(),
+            source: e,
+        })?;
+
+    // A command killed by a signal exits like it would from a shell
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        return Ok(128 + signal);
+    }
+
+    Ok(status.code().unwrap_or(1))
+}
+
+fn handle_proxy(
+    profile_opt: Option<&str>,
+    listen: String,
+    allow_remote: bool,
+    upstream: Option<String>,
+) -> Result<()> {
+    let Detection {
+        profile: profile_name,
+        project,
+        ..
+    } = detect(profile_opt)?;
+    let profile = ProfileManager::get(&profile_name)?;
+
+    let project_upstream = project_env(project.as_ref())?.remove("ANTHROPIC_BASE_URL");
+    let upstream = match upstream.or(project_upstream) {
+        Some(upstream) => upstream,
+        None => crate::core::config::load()?.proxy.upstream,
+    };
+
+    let listener = proxy::bind(&listen, allow_remote)?;
+    let session_token = proxy::session_token();
+    let credential = proxy::profile_credential(profile_name.clone(), profile.credential_type);
+
+    eprintln!(
+        "Proxying http://{} -> {} with profile '{}' ({})",
+        listener.local_addr()?,
+        upstream,
+        profile_name,
+        profile.credential_type
+    );
+    eprintln!("  export ANTHROPIC_BASE_URL=http://{}", listener.local_addr()?);
+    eprintln!("  export ANTHROPIC_API_KEY={}", session_token);
+
+    proxy::serve(
+        listener,
+        &upstream,
+        session_token,
+        profile.credential_type,
+        credential,
+    )
+}
+
+/// `env` output in structured formats
+#[derive(Serialize)]
+struct EnvOutput {
+    profile: String,
+    credential_type: CredentialType,
+    variables: BTreeMap<String, String>,
+}
+
+fn handle_env(format: OutputFormat, profile_opt: Option<&str>) -> Result<()> {
     // Resolve profile name
-    let profile_name = resolve_profile(profile_opt)?;
+    let Detection {
+        profile: profile_name,
+        project,
+        ..
+    } = detect(profile_opt)?;
+    let project_env = project_env(project.as_ref())?;
 
     // Ensure token is valid (auto-refresh if expired)
     crate::core::ensure_token_valid(&profile_name)?;
@@ -233,31 +790,59 @@ fn handle_env(profile_opt: Option<String>) -> Result<()> {
     // Get profile to check credential type and expiration
     let profile = ProfileManager::get(&profile_name)?;
 
-    // Warn if expiring soon (within 24 hours)
-    if profile.expires_soon() {
+    // Warn if expiring soon
+    if profile.expires_soon(&crate::core::config::load()?.expiry) {
         eprintln!("# Warning: Profile '{}' credentials expire soon", profile_name);
     }
 
     // Get credential from keychain based on type
     let credential = crate::core::keychain::get_by_type(&profile_name, profile.credential_type)?;
 
+    if format != OutputFormat::Table {
+        let mut variables = project_env;
+        variables.insert("ANTHROPIC_API_KEY".to_string(), credential);
+
+        return output::print(
+            format,
+            &EnvOutput {
+                profile: profile_name,
+                credential_type: profile.credential_type,
+                variables,
+            },
+        );
+    }
+
     // Print export statement for shell integration
+    for (name, value) in &project_env {
+        println!("export {}={}", name, shell_quote(value));
+    }
     println!("export ANTHROPIC_API_KEY=\"{}\"", credential);
     println!("# Profile: {} ({})", profile_name, profile.credential_type);
 
     Ok(())
 }
 
-/// Resolve profile name from option, detection, or default
-fn resolve_profile(profile_opt: Option<String>) -> Result<String> {
-    if let Some(name) = profile_opt {
-        // Verify profile exists
-        ProfileManager::get(&name)?;
-        Ok(name)
-    } else {
-        // Try to detect profile, fallback to default
-        detect_profile()
+/// Environment variables a project's `.claude-profile` sets
+fn project_env(project: Option<&ProjectProfile>) -> Result<BTreeMap<String, String>> {
+    let Some(project) = project else {
+        return Ok(BTreeMap::new());
+    };
+    let vars = project.env_vars(&crate::core::config::load()?.proxy.allowed_upstreams)?;
+
+    // The credential goes wherever the base URL points, so never redirect it silently
+    if let Some(base_url) = vars.get("ANTHROPIC_BASE_URL") {
+        eprintln!(
+            "ℹ️  .claude-profile sets ANTHROPIC_BASE_URL; credentials will be sent to {}",
+            base_url
+        );
     }
+
+    Ok(vars)
+}
+
+/// Quote a value for POSIX shells
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
 }
 
 fn handle_completion(shell: Shell) -> Result<()> {
@@ -287,7 +872,7 @@ fn handle_completion(shell: Shell) -> Result<()> {
     Ok(())
 }
 
-fn handle_import(import_type: String, profile_opt: Option<String>) -> Result<()> {
+fn handle_import(import_type: String, profile_opt: Option<&str>) -> Result<()> {
     if import_type != "oauth" {
         return Err(crate::error::Error::ConfigError(format!(
             "Unknown import type '{}'. Currently only 'oauth' is supported.",
@@ -295,73 +880,36 @@ fn handle_import(import_type: String, profile_opt: Option<String>) -> Result<()>
         )));
     }
 
-    let profile_name = profile_opt.unwrap_or_else(|| "default".to_string());
+    let profile_name = detector::pinned_or(profile_opt, "default");
 
     println!("Importing OAuth token from Claude Code...");
     println!("Profile: {}", profile_name);
 
-    // Try to read OAuth token from Claude Code's keychain entry
-    // Claude Code uses "Claude Code-credentials" as service name and username as account
-    let claude_code_service = "Claude Code-credentials";
-
-    // Get current username for keychain account
-    let username = std::env::var("USER")
-        .or_else(|_| std::env::var("USERNAME"))
-        .map_err(|_| crate::error::Error::ConfigError(
-            "Could not determine username. Please set USER or USERNAME environment variable.".to_string()
-        ))?;
-
-    let entry = keyring::Entry::new(claude_code_service, &username)
-        .map_err(|e| crate::error::Error::KeychainError(format!(
-            "Failed to access Claude Code keychain: {}", e
-        )))?;
-
-    let credentials_json = entry
-        .get_password()
-        .map_err(|e| crate::error::Error::KeychainError(format!(
-            "Failed to retrieve OAuth token from Claude Code.\n\
-             Make sure you are logged in to Claude Code (run: claude /login)\n\
-             Error: {}", e
-        )))?;
-
-    if credentials_json.is_empty() {
-        return Err(crate::error::Error::ConfigError(
-            "OAuth credentials are empty. Please login to Claude Code first.".to_string(),
-        ));
-    }
+    // Claude Code keeps its credentials in the keychain (macOS) or a file (Linux)
+    let storage = crate::core::claude_code::CredentialStorage::detect()?;
+    println!("Source: {}", storage);
 
-    // Parse JSON to extract access token and expiration
-    let credentials: serde_json::Value = serde_json::from_str(&credentials_json)
-        .map_err(|e| crate::error::Error::ConfigError(format!(
-            "Failed to parse Claude Code credentials: {}", e
-        )))?;
-
-    let oauth_token = credentials["claudeAiOauth"]["accessToken"]
-        .as_str()
-        .ok_or_else(|| crate::error::Error::ConfigError(
-            "Could not find accessToken in Claude Code credentials.".to_string()
-        ))?
-        .to_string();
-
-    // Extract refresh token
-    let refresh_token = credentials["claudeAiOauth"]["refreshToken"]
-        .as_str()
-        .ok_or_else(|| crate::error::Error::ConfigError(
-            "Could not find refreshToken in Claude Code credentials.".to_string()
-        ))?
-        .to_string();
-
-    // Parse expiration time (in milliseconds since epoch)
-    let expires_at = credentials["claudeAiOauth"]["expiresAt"]
-        .as_i64()
-        .and_then(|ms| {
-            chrono::DateTime::from_timestamp(ms / 1000, ((ms % 1000) * 1_000_000) as u32)
-        });
+    let credentials_json = storage.read()?.ok_or_else(|| {
+        crate::error::Error::KeychainError(format!(
+            "No OAuth credentials found in Claude Code's {}.\n\
+             Make sure you are logged in to Claude Code (run: claude /login)",
+            storage
+        ))
+    })?;
+
+    // Parse JSON to extract tokens and expiration
+    let oauth = crate::core::claude_code::ClaudeCodeOAuth::parse(&credentials_json)?;
+    let identity = oauth.identity();
+    let oauth_token = oauth.access_token;
+    let expires_at = oauth.expires_at;
 
-    // Extract subscription type if available
-    let subscription_type = credentials["claudeAiOauth"]["subscriptionType"]
-        .as_str()
-        .unwrap_or("unknown");
+    let refresh_token = oauth.refresh_token.ok_or_else(|| crate::error::Error::ConfigError(
+        "Could not find refreshToken in Claude Code credentials.".to_string()
+    ))?;
+
+    // Subscription type if available
+    let subscription_type = oauth.subscription_type.as_deref().unwrap_or("unknown");
+    let account = identity.account.clone();
 
     // Import the OAuth token
     let description = Some(format!(
@@ -370,7 +918,13 @@ fn handle_import(import_type: String, profile_opt: Option<String>) -> Result<()>
         chrono::Utc::now().format("%Y-%m-%d")
     ));
 
-    let profile = ProfileManager::add_oauth(&profile_name, description, &oauth_token, expires_at)?;
+    let profile = ProfileManager::add_oauth(
+        &profile_name,
+        description,
+        &oauth_token,
+        expires_at,
+        identity,
+    )?;
 
     // Store refresh token in keychain
     crate::core::keychain::store_refresh_token(&profile_name, &refresh_token)?;
@@ -379,6 +933,9 @@ fn handle_import(import_type: String, profile_opt: Option<String>) -> Result<()>
     println!("  Profile: {}", profile.name);
     println!("  Type: {}", profile.credential_type);
     println!("  Subscription: {}", subscription_type);
+    if let Some(email) = account.and_then(|account| account.email) {
+        println!("  Account: {}", email);
+    }
     println!("  Created: {}", profile.created_at.to_rfc3339());
 
     if let Some(exp) = expires_at {
@@ -401,3 +958,461 @@ fn handle_import(import_type: String, profile_opt: Option<String>) -> Result<()>
 
     Ok(())
 }
+
+fn handle_switch(
+    profile_opt: Option<String>,
+    back: bool,
+    capture_as: Option<String>,
+) -> Result<()> {
+    use crate::core::claude_code;
+
+    // clap only catches a positional profile; --profile is global
+    if back && profile_opt.is_some() {
+        return Err(crate::error::Error::ConfigError(
+            "--back cannot be combined with a profile".to_string(),
+        ));
+    }
+
+    let target = if back {
+        crate::core::config::load()?
+            .claude_code
+            .previous_profile
+            .ok_or_else(|| {
+                crate::error::Error::ConfigError(
+                    "No previous Claude Code account recorded".to_string(),
+                )
+            })?
+    } else {
+        match profile_opt {
+            Some(profile) => profile,
+            None => {
+                let state = crate::core::config::load()?.claude_code;
+                match state.active_profile {
+                    Some(active) => println!("Claude Code is using profile: {}", active),
+                    None => println!("Claude Code is not switched to a profile"),
+                }
+                if let Some(previous) = state.previous_profile {
+                    println!(
+                        "Previous profile: {} (switch back with: claude-vault switch --back)",
+                        previous
+                    );
+                }
+                return Ok(());
+            }
+        }
+    };
+
+    // Hand Claude Code a token that is still good for a while
+    crate::core::ensure_token_valid(&target)?;
+
+    let switched = claude_code::switch(&target, capture_as.as_deref())?;
+
+    if let Some(ref captured) = switched.captured {
+        println!(
+            "✓ Saved the account Claude Code was logged in as to new profile '{}'",
+            captured
+        );
+    }
+
+    println!("✓ Claude Code switched to profile '{}'", target);
+    println!("  Credentials: {}", switched.storage);
+    if let Some(previous) = switched.previous.filter(|previous| *previous != target) {
+        println!(
+            "  Previous: {} (switch back with: claude-vault switch --back)",
+            previous
+        );
+    }
+    println!("  Restart running Claude Code sessions to pick up the new account");
+
+    Ok(())
+}
+
+fn handle_recover() -> Result<()> {
+    use crate::core::claude_code::{self, Recovery};
+
+    match claude_code::recover()? {
+        Recovery::Nothing => {
+            println!("✓ Nothing to recover; Claude Code credentials were not left switched");
+        }
+        Recovery::InUse(journal) => {
+            let owner = journal
+                .map(|journal| format!(" (profile '{}', pid {})", journal.profile, journal.pid))
+                .unwrap_or_default();
+            return Err(crate::error::Error::ConfigError(format!(
+                "Claude Code is switched by a running claude-vault process{}; it restores the credentials when it exits",
+                owner
+            )));
+        }
+        Recovery::Restored(journal) => {
+            println!("✓ Restored Claude Code credentials");
+            println!(
+                "  Switched to profile '{}' by pid {} at {}",
+                journal.profile,
+                journal.pid,
+                journal.started_at.to_rfc3339()
+            );
+            if journal.backup.is_none() {
+                println!("  There were no credentials before the switch; Claude Code is logged out");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Collect `login` flags into per-profile OAuth overrides
+fn oauth_overrides(
+    authorize_url: Option<String>,
+    token_url: Option<String>,
+    client_id: Option<String>,
+    scopes: Vec<String>,
+    params: Vec<String>,
+) -> Result<OAuthOverrides> {
+    let extra_params = params
+        .iter()
+        .map(|param| parse_key_value(param))
+        .collect::<Result<BTreeMap<_, _>>>()?;
+
+    Ok(OAuthOverrides {
+        authorize_endpoint: authorize_url,
+        token_endpoint: token_url,
+        client_id,
+        scopes: if scopes.is_empty() { None } else { Some(scopes) },
+        extra_params,
+    })
+}
+
+fn handle_login(
+    profile_opt: Option<&str>,
+    overrides: OAuthOverrides,
+    port: u16,
+    no_browser: bool,
+) -> Result<()> {
+    let profile_name = detector::pinned_or(profile_opt, "default");
+
+    // Flags add to whatever the profile was configured with before
+    let config = crate::core::config::load()?;
+    let overrides = config
+        .find_profile(&profile_name)
+        .map(|profile| profile.oauth.merged_with(&overrides))
+        .unwrap_or(overrides);
+
+    println!("Logging in with OAuth...");
+    println!("Profile: {}", profile_name);
+
+    let profile = crate::core::login::login(&profile_name, &config.oauth, &overrides, port, |url| {
+        println!();
+        println!("Open this URL in your browser to authorize claude-vault:");
+        println!("  {}", url);
+        println!();
+
+        if !no_browser {
+            open_browser(url.as_str());
+        }
+
+        println!("Waiting for authorization...");
+    })?;
+
+    println!("✓ Logged in successfully");
+    println!("  Profile: {}", profile.name);
+    println!("  Type: {}", profile.credential_type);
+    if let Some(exp) = profile.expires_at {
+        println!("  Expires: {}", exp.to_rfc3339());
+    }
+
+    Ok(())
+}
+
+/// Best-effort attempt to open a URL in the default browser
+fn open_browser(url: &str) {
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(windows) {
+        "explorer"
+    } else {
+        "xdg-open"
+    };
+
+    let _ = Command::new(opener)
+        .arg(url)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn();
+}
+
+#[cfg(unix)]
+fn handle_agent(action: AgentAction) -> Result<()> {
+    use crate::core::agent::{self, AgentClient, AGENT_SOCK_ENV};
+    use std::os::unix::process::CommandExt;
+    use std::process::Stdio;
+    use std::time::Duration;
+
+    let client = || -> Result<AgentClient> {
+        match AgentClient::from_env() {
+            Some(client) => Ok(client),
+            None => Ok(AgentClient::new(agent::default_socket_path()?)),
+        }
+    };
+
+    match action {
+        AgentAction::Start {
+            socket,
+            ttl,
+            foreground,
+        } => {
+            let socket = match socket {
+                Some(socket) => socket,
+                None => agent::default_socket_path()?,
+            };
+            let ttl = match ttl {
+                Some(ttl) => ttl,
+                None => crate::core::config::load()?.agent.idle_ttl_seconds,
+            };
+
+            if foreground {
+                eprintln!("Agent listening on {}", socket.display());
+                return agent::serve(&socket, Duration::from_secs(ttl));
+            }
+
+            let probe = AgentClient::new(socket.clone());
+            if probe.list().is_ok() {
+                return Err(crate::error::Error::AgentError(format!(
+                    "An agent is already listening on {}",
+                    socket.display()
+                )));
+            }
+
+            // Re-run ourselves detached in a new process group
+            let child = Command::new(std::env::current_exe()?)
+                .args(["agent", "start", "--foreground", "--ttl", &ttl.to_string()])
+                .arg("--socket")
+                .arg(&socket)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .process_group(0)
+                .spawn()
+                .map_err(|e| {
+                    crate::error::Error::AgentError(format!("Failed to start agent: {}", e))
+                })?;
+
+            let mut ready = false;
+            for _ in 0..50 {
+                if probe.list().is_ok() {
+                    ready = true;
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+
+            if !ready {
+                return Err(crate::error::Error::AgentError(
+                    "Agent did not start listening in time".to_string(),
+                ));
+            }
+
+            println!(
+                "{}=\"{}\"; export {};",
+                AGENT_SOCK_ENV,
+                socket.display(),
+                AGENT_SOCK_ENV
+            );
+            println!("echo Agent pid {};", child.id());
+        }
+        AgentAction::Stop => {
+            client()?.stop()?;
+            println!("✓ Agent stopped");
+        }
+        AgentAction::Lock => {
+            let password = Password::new()
+                .with_prompt("Agent lock password")
+                .with_confirmation("Confirm password", "Passwords do not match")
+                .interact()
+                .map_err(|e| {
+                    crate::error::Error::ConfigError(format!("Failed to read input: {}", e))
+                })?;
+
+            client()?.lock(&password)?;
+            println!("✓ Agent locked");
+        }
+        AgentAction::Unlock => {
+            let password = Password::new()
+                .with_prompt("Agent lock password")
+                .interact()
+                .map_err(|e| {
+                    crate::error::Error::ConfigError(format!("Failed to read input: {}", e))
+                })?;
+
+            client()?.unlock(&password)?;
+            println!("✓ Agent unlocked");
+        }
+        AgentAction::List => {
+            let entries = client()?.list()?;
+
+            if entries.is_empty() {
+                println!("No credentials cached.");
+                return Ok(());
+            }
+
+            println!("Cached credentials:");
+            for entry in entries {
+                println!(
+                    "  • {} ({}) - idle {}s, forgotten in {}s",
+                    entry.profile, entry.kind, entry.idle_seconds, entry.expires_in_seconds
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn handle_agent(_action: AgentAction) -> Result<()> {
+    Err(crate::error::Error::ConfigError(
+        "The credential agent is only supported on Unix".to_string(),
+    ))
+}
+
+fn handle_config(action: ConfigAction) -> Result<()> {
+    use crate::core::config;
+
+    match action {
+        ConfigAction::Migrate { dry_run: true } => {
+            let plan = match config::plan_migration()? {
+                Some(plan) => plan,
+                None => {
+                    println!("✓ config.toml is up to date");
+                    return Ok(());
+                }
+            };
+
+            println!(
+                "config.toml would be migrated from version {} to {}:",
+                plan.migrated.from, plan.migrated.to
+            );
+            for step in &plan.migrated.steps {
+                println!("  • {}", step);
+            }
+            println!();
+
+            let diff = similar::TextDiff::from_lines(&plan.original, &plan.upgraded);
+            print!(
+                "{}",
+                diff.unified_diff()
+                    .header("config.toml", "config.toml (migrated)")
+            );
+            println!();
+            println!("Run 'claude-vault config migrate' to apply (a backup is kept)");
+        }
+        ConfigAction::Migrate { dry_run: false } => match config::migrate_file()? {
+            Some((migrated, backup_path)) => {
+                println!(
+                    "✓ Migrated config.toml from version {} to {}",
+                    migrated.from, migrated.to
+                );
+                for step in &migrated.steps {
+                    println!("  • {}", step);
+                }
+                println!("  Backup: {}", backup_path.display());
+            }
+            None => println!("✓ config.toml is up to date"),
+        },
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct RuleTest {
+    path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rule: Option<crate::types::Rule>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    matched: Option<crate::core::rules::Matched>,
+}
+
+fn handle_rule(format: OutputFormat, action: RuleAction) -> Result<()> {
+    use crate::core::config;
+    use crate::core::rules::{self, Matched};
+    use crate::types::RuleTarget;
+
+    let target = |pattern: String, remote: bool| match remote {
+        true => RuleTarget::Remote(pattern),
+        false => RuleTarget::Path(pattern),
+    };
+
+    match action {
+        RuleAction::Add {
+            pattern,
+            profile,
+            remote,
+        } => {
+            let rule = rules::add(target(pattern, remote), &profile)?;
+            match rule.target {
+                RuleTarget::Path(pattern) => println!(
+                    "✓ Directories matching '{}' use profile '{}'",
+                    pattern, profile
+                ),
+                RuleTarget::Remote(pattern) => println!(
+                    "✓ Repositories with a remote matching '{}' use profile '{}'",
+                    pattern, profile
+                ),
+            }
+        }
+        RuleAction::List => {
+            let rules = config::load()?.rules;
+            if format != OutputFormat::Table {
+                return output::print(format, &rules);
+            }
+
+            if rules.is_empty() {
+                println!("No rules configured.");
+                println!("Add one with: claude-vault rule add '<pattern>' <profile>");
+                return Ok(());
+            }
+
+            println!("Rules:");
+            for rule in rules {
+                println!("  • {} → {}", rule.target, rule.profile);
+            }
+        }
+        RuleAction::Remove { pattern, remote } => {
+            let rule = rules::remove(&target(pattern, remote))?;
+            println!("✓ Removed rule {} → {}", rule.target, rule.profile);
+        }
+        RuleAction::Test { path } => {
+            let path = std::path::absolute(path.unwrap_or_else(|| PathBuf::from(".")))?;
+            let config = config::load()?;
+            let found = rules::find_match(&config.rules, &path);
+            let result = RuleTest {
+                path,
+                rule: found.as_ref().map(|found| found.rule.clone()),
+                matched: found.map(|found| found.matched),
+            };
+
+            if format != OutputFormat::Table {
+                return output::print(format, &result);
+            }
+
+            match (result.rule, result.matched) {
+                (Some(rule), Some(matched)) => {
+                    println!("{} → {}", result.path.display(), rule.profile);
+                    println!("  Rule: {}", rule.target);
+                    match matched {
+                        Matched::Directory { path } if path != result.path => {
+                            println!("  Matched: {}", path.display())
+                        }
+                        Matched::Directory { .. } => {}
+                        Matched::Remote { name, url } => {
+                            println!("  Matched: remote '{}' ({})", name, url)
+                        }
+                    }
+                }
+                _ => println!("No rule matches {}", result.path.display()),
+            }
+        }
+    }
+
+    Ok(())
+}
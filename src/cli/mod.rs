@@ -1,11 +1,58 @@
 mod commands;
 mod handlers;
+mod output;
 
 use crate::error::Result;
 use clap::Parser;
 use commands::Cli;
 
-pub fn run() -> Result<()> {
+/// Run the CLI, reporting any error in the requested format; returns the exit code
+pub fn run() -> i32 {
     let cli = Cli::parse();
+    let format = cli.format;
+    let is_exec = matches!(cli.command, commands::Commands::Exec { .. });
+
+    match run_command(cli) {
+        Ok(()) => 0,
+        Err(e) => {
+            // exec passes the command's exit code through; keep ours apart from it
+            let exit_code = if is_exec {
+                e.exec_exit_code()
+            } else {
+                e.exit_code()
+            };
+            output::print_error(format, &e, exit_code);
+            exit_code
+        }
+    }
+}
+
+fn run_command(cli: Cli) -> Result<()> {
+    if let Some(ref vault_dir) = cli.vault_dir {
+        // Set for the whole process (and children such as the agent) before any path is resolved
+        std::env::set_var(crate::core::config::VAULT_HOME_ENV, vault_dir);
+    }
+
+    match crate::core::config::migrate_legacy_dir() {
+        Ok(moved) if !moved.is_empty() => {
+            let config_dir = crate::core::config::get_config_dir()?;
+            eprintln!(
+                "ℹ️  Moved {} claude-vault files from ~/.claude-vault to the XDG directories (config: {})",
+                moved.len(),
+                config_dir.display()
+            );
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!(
+            "⚠️  Warning: Failed to move files from ~/.claude-vault: {}",
+            e
+        ),
+    }
+
+    // A previous run may have died with Claude Code still switched to a profile
+    if !matches!(cli.command, commands::Commands::Recover) {
+        crate::core::claude_code::recover_interrupted();
+    }
+
     handlers::handle_command(cli)
 }
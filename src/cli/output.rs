@@ -0,0 +1,53 @@
+use crate::cli::commands::OutputFormat;
+use crate::error::{Error, Result};
+use serde::Serialize;
+
+/// Print `value` in a structured format (JSON unless YAML was asked for)
+pub fn print<T: Serialize>(format: OutputFormat, value: &T) -> Result<()> {
+    match format {
+        OutputFormat::Yaml => print!("{}", serde_yaml_ng::to_string(value)?),
+        OutputFormat::Json | OutputFormat::Table => {
+            println!("{}", serde_json::to_string_pretty(value)?)
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct ErrorReport {
+    error: ErrorDetails,
+}
+
+#[derive(Serialize)]
+struct ErrorDetails {
+    /// Stable code from `Error::code`
+    code: &'static str,
+    message: String,
+    exit_code: i32,
+}
+
+/// Report an error on stderr in the requested format
+pub fn print_error(format: OutputFormat, error: &Error, exit_code: i32) {
+    if format == OutputFormat::Table {
+        eprintln!("Error: {}", error);
+        return;
+    }
+
+    let report = ErrorReport {
+        error: ErrorDetails {
+            code: error.code(),
+            message: error.to_string(),
+            exit_code,
+        },
+    };
+    let rendered = match format {
+        OutputFormat::Yaml => serde_yaml_ng::to_string(&report).ok(),
+        _ => serde_json::to_string_pretty(&report).ok(),
+    };
+
+    match rendered {
+        Some(rendered) => eprintln!("{}", rendered.trim_end()),
+        None => eprintln!("Error: {}", error),
+    }
+}
@@ -0,0 +1,588 @@
+use crate::core::{config, store::SecretKind};
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Environment variable pointing clients at a running agent
+pub const AGENT_SOCK_ENV: &str = "CLAUDE_VAULT_AGENT_SOCK";
+
+const SOCKET_FILE_NAME: &str = "agent.sock";
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Request {
+    Get {
+        kind: SecretKind,
+        profile: String,
+    },
+    Add {
+        kind: SecretKind,
+        profile: String,
+        secret: String,
+    },
+    Remove {
+        kind: SecretKind,
+        profile: String,
+    },
+    List,
+    Lock {
+        password: String,
+    },
+    Unlock {
+        password: String,
+    },
+    Stop,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum Response {
+    Ok,
+    Secret { secret: String },
+    Miss,
+    Entries { entries: Vec<AgentEntry> },
+    Locked,
+    Error { message: String },
+}
+
+/// Answer to a lookup in the agent
+#[derive(Debug, PartialEq, Eq)]
+pub enum Lookup {
+    Hit(String),
+    Miss,
+    /// The agent is locked and serves nothing until unlocked
+    Locked,
+}
+
+/// A cached secret as reported by `agent list` (never the secret itself)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AgentEntry {
+    pub kind: SecretKind,
+    pub profile: String,
+    pub idle_seconds: u64,
+    pub expires_in_seconds: u64,
+}
+
+struct CachedSecret {
+    secret: String,
+    last_used: Instant,
+}
+
+/// In-memory state of a running agent
+struct AgentState {
+    entries: HashMap<(SecretKind, String), CachedSecret>,
+    idle_ttl: Duration,
+    lock_password: Option<String>,
+}
+
+impl AgentState {
+    fn new(idle_ttl: Duration) -> Self {
+        Self {
+            entries: HashMap::new(),
+            idle_ttl,
+            lock_password: None,
+        }
+    }
+
+    /// Drop every secret that has not been used within the idle TTL
+    fn expire(&mut self) {
+        let idle_ttl = self.idle_ttl;
+        self.entries
+            .retain(|_, cached| cached.last_used.elapsed() < idle_ttl);
+    }
+
+    fn handle(&mut self, request: Request) -> Response {
+        self.expire();
+
+        if self.lock_password.is_some() {
+            match request {
+                // Evicting reveals nothing and keeps the cache from going stale
+                Request::Unlock { .. } | Request::Remove { .. } | Request::Stop => {}
+                Request::Lock { .. } => {
+                    return Response::Error {
+                        message: "Agent is already locked".to_string(),
+                    }
+                }
+                _ => return Response::Locked,
+            }
+        }
+
+        match request {
+            Request::Get { kind, profile } => match self.entries.get_mut(&(kind, profile)) {
+                Some(cached) => {
+                    cached.last_used = Instant::now();
+                    Response::Secret {
+                        secret: cached.secret.clone(),
+                    }
+                }
+                None => Response::Miss,
+            },
+            Request::Add {
+                kind,
+                profile,
+                secret,
+            } => {
+                self.entries.insert(
+                    (kind, profile),
+                    CachedSecret {
+                        secret,
+                        last_used: Instant::now(),
+                    },
+                );
+                Response::Ok
+            }
+            Request::Remove { kind, profile } => {
+                self.entries.remove(&(kind, profile));
+                Response::Ok
+            }
+            Request::List => {
+                let mut entries: Vec<AgentEntry> = self
+                    .entries
+                    .iter()
+                    .map(|((kind, profile), cached)| {
+                        let idle = cached.last_used.elapsed();
+                        AgentEntry {
+                            kind: *kind,
+                            profile: profile.clone(),
+                            idle_seconds: idle.as_secs(),
+                            expires_in_seconds: self.idle_ttl.saturating_sub(idle).as_secs(),
+                        }
+                    })
+                    .collect();
+                entries.sort_by(|a, b| (&a.profile, a.kind).cmp(&(&b.profile, b.kind)));
+                Response::Entries { entries }
+            }
+            Request::Lock { password } => {
+                if password.is_empty() {
+                    return Response::Error {
+                        message: "Lock password cannot be empty".to_string(),
+                    };
+                }
+                // Locking forgets every secret; they are fetched again after unlock
+                self.entries.clear();
+                self.lock_password = Some(password);
+                Response::Ok
+            }
+            Request::Unlock { password } => match self.lock_password {
+                Some(ref expected) if *expected == password => {
+                    self.lock_password = None;
+                    Response::Ok
+                }
+                Some(_) => Response::Error {
+                    message: "Incorrect password".to_string(),
+                },
+                None => Response::Error {
+                    message: "Agent is not locked".to_string(),
+                },
+            },
+            Request::Stop => Response::Ok,
+        }
+    }
+}
+
+/// Default socket path in the state directory
+pub fn default_socket_path() -> Result<PathBuf> {
+    let state_dir = config::get_state_dir()?;
+    Ok(state_dir.join(SOCKET_FILE_NAME))
+}
+
+/// Run the agent in the foreground until it receives a stop request
+pub fn serve(socket_path: &Path, idle_ttl: Duration) -> Result<()> {
+    if socket_path.exists() {
+        if AgentClient::new(socket_path.to_path_buf()).list().is_ok() {
+            return Err(Error::AgentError(format!(
+                "An agent is already listening on {}",
+                socket_path.display()
+            )));
+        }
+        // Stale socket from an agent that died
+        fs::remove_file(socket_path)?;
+    }
+
+    if let Some(parent) = socket_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let listener = bind_private(socket_path)?;
+
+    let state = Arc::new(Mutex::new(AgentState::new(idle_ttl)));
+    let stop = Arc::new(AtomicBool::new(false));
+
+    // Wipe idle secrets even when no requests arrive
+    let sweeper_state = Arc::downgrade(&state);
+    thread::spawn(move || loop {
+        thread::sleep(SWEEP_INTERVAL);
+        match sweeper_state.upgrade() {
+            Some(state) => state.lock().unwrap_or_else(|e| e.into_inner()).expire(),
+            None => break,
+        }
+    });
+
+    for stream in listener.incoming() {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+
+        // Each client gets its own thread, so an idle one can't hold up the others;
+        // a misbehaving client must not take the agent down
+        let state = Arc::clone(&state);
+        let stop = Arc::clone(&stop);
+        let socket_path = socket_path.to_path_buf();
+        thread::spawn(move || {
+            if let Ok(true) = handle_connection(stream, &state) {
+                stop.store(true, Ordering::SeqCst);
+                // Wake the accept loop so it sees the stop flag
+                let _ = UnixStream::connect(&socket_path);
+            }
+        });
+    }
+
+    let _ = fs::remove_file(socket_path);
+
+    Ok(())
+}
+
+/// Bind the socket so it is never reachable with default permissions: bind
+/// it inside a fresh 0700 directory, restrict it to 0600, then move it into place
+fn bind_private(socket_path: &Path) -> Result<UnixListener> {
+    let parent = socket_path.parent().unwrap_or_else(|| Path::new("."));
+    let staging = parent.join(format!(".agent-{}", rand::random::<u64>()));
+    fs::DirBuilder::new().mode(0o700).create(&staging)?;
+
+    let staged = staging.join(SOCKET_FILE_NAME);
+    let result = UnixListener::bind(&staged).and_then(|listener| {
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&staged, socket_path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&staged);
+    let _ = fs::remove_dir(&staging);
+
+    Ok(result?)
+}
+
+/// Serve a single request; returns true when the agent should stop
+fn handle_connection(stream: UnixStream, state: &Mutex<AgentState>) -> Result<bool> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+
+    let (response, stop) = match serde_json::from_str::<Request>(&line) {
+        Ok(request) => {
+            let stop = matches!(request, Request::Stop);
+            let response = state
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .handle(request);
+            (response, stop)
+        }
+        Err(e) => (
+            Response::Error {
+                message: format!("Invalid request: {}", e),
+            },
+            false,
+        ),
+    };
+
+    let mut writer = &stream;
+    writeln!(writer, "{}", serde_json::to_string(&response)?)?;
+
+    Ok(stop)
+}
+
+/// Client for a running agent
+pub struct AgentClient {
+    socket_path: PathBuf,
+}
+
+impl AgentClient {
+    pub fn new(socket_path: PathBuf) -> Self {
+        Self { socket_path }
+    }
+
+    /// Client for the agent named by `CLAUDE_VAULT_AGENT_SOCK`, if set
+    pub fn from_env() -> Option<Self> {
+        std::env::var_os(AGENT_SOCK_ENV)
+            .filter(|path| !path.is_empty())
+            .map(|path| Self::new(PathBuf::from(path)))
+    }
+
+    fn connect_error(&self, e: std::io::Error) -> Error {
+        Error::AgentError(format!(
+            "Failed to connect to agent at {}: {}",
+            self.socket_path.display(),
+            e
+        ))
+    }
+
+    fn request(&self, request: &Request) -> Result<Response> {
+        let stream = UnixStream::connect(&self.socket_path).map_err(|e| self.connect_error(e))?;
+        self.exchange(stream, request)
+    }
+
+    fn exchange(&self, stream: UnixStream, request: &Request) -> Result<Response> {
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+        let mut writer = &stream;
+        writeln!(writer, "{}", serde_json::to_string(request)?)?;
+
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+
+        match serde_json::from_str(&line)? {
+            Response::Error { message } => Err(Error::AgentError(message)),
+            response => Ok(response),
+        }
+    }
+
+    fn expect_ok(&self, request: &Request) -> Result<()> {
+        match self.request(request)? {
+            Response::Ok => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Look up a cached secret
+    pub fn get(&self, kind: SecretKind, profile: &str) -> Result<Lookup> {
+        let request = Request::Get {
+            kind,
+            profile: profile.to_string(),
+        };
+        match self.request(&request)? {
+            Response::Secret { secret } => Ok(Lookup::Hit(secret)),
+            Response::Miss => Ok(Lookup::Miss),
+            Response::Locked => Ok(Lookup::Locked),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub fn add(&self, kind: SecretKind, profile: &str, secret: &str) -> Result<()> {
+        self.expect_ok(&Request::Add {
+            kind,
+            profile: profile.to_string(),
+            secret: secret.to_string(),
+        })
+    }
+
+    /// Drop a cached secret so the agent can't go on serving an outdated copy;
+    /// succeeds when no agent is listening, since then nothing is cached
+    pub fn evict(&self, kind: SecretKind, profile: &str) -> Result<()> {
+        let stream = match UnixStream::connect(&self.socket_path) {
+            Ok(stream) => stream,
+            Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) => {
+                return Ok(())
+            }
+            Err(e) => return Err(self.connect_error(e)),
+        };
+
+        let request = Request::Remove {
+            kind,
+            profile: profile.to_string(),
+        };
+        match self.exchange(stream, &request)? {
+            Response::Ok => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub fn list(&self) -> Result<Vec<AgentEntry>> {
+        match self.request(&Request::List)? {
+            Response::Entries { entries } => Ok(entries),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub fn lock(&self, password: &str) -> Result<()> {
+        self.expect_ok(&Request::Lock {
+            password: password.to_string(),
+        })
+    }
+
+    pub fn unlock(&self, password: &str) -> Result<()> {
+        self.expect_ok(&Request::Unlock {
+            password: password.to_string(),
+        })
+    }
+
+    pub fn stop(&self) -> Result<()> {
+        self.expect_ok(&Request::Stop)
+    }
+}
+
+fn unexpected(response: Response) -> Error {
+    match response {
+        Response::Locked => Error::AgentError("Agent is locked".to_string()),
+        other => Error::AgentError(format!("Unexpected agent response: {:?}", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn add(state: &mut AgentState, profile: &str, secret: &str) -> Response {
+        state.handle(Request::Add {
+            kind: SecretKind::OAuthToken,
+            profile: profile.to_string(),
+            secret: secret.to_string(),
+        })
+    }
+
+    fn get(state: &mut AgentState, profile: &str) -> Response {
+        state.handle(Request::Get {
+            kind: SecretKind::OAuthToken,
+            profile: profile.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_agent_state_get_and_list() {
+        let mut state = AgentState::new(Duration::from_secs(60));
+
+        assert!(matches!(get(&mut state, "work"), Response::Miss));
+        assert!(matches!(add(&mut state, "work", "token"), Response::Ok));
+        assert!(matches!(
+            get(&mut state, "work"),
+            Response::Secret { ref secret } if secret == "token"
+        ));
+
+        match state.handle(Request::List) {
+            Response::Entries { entries } => {
+                assert_eq!(entries.len(), 1);
+                assert_eq!(entries[0].profile, "work");
+                assert_eq!(entries[0].kind, SecretKind::OAuthToken);
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_agent_state_idle_expiry() {
+        let mut state = AgentState::new(Duration::ZERO);
+
+        add(&mut state, "work", "token");
+        assert!(matches!(get(&mut state, "work"), Response::Miss));
+    }
+
+    #[test]
+    fn test_agent_state_lock_unlock() {
+        let mut state = AgentState::new(Duration::from_secs(60));
+        add(&mut state, "work", "token");
+
+        assert!(matches!(
+            state.handle(Request::Lock {
+                password: "hunter2".to_string()
+            }),
+            Response::Ok
+        ));
+        assert!(matches!(get(&mut state, "work"), Response::Locked));
+        assert!(matches!(state.handle(Request::List), Response::Locked));
+        assert!(matches!(
+            state.handle(Request::Remove {
+                kind: SecretKind::OAuthToken,
+                profile: "work".to_string(),
+            }),
+            Response::Ok
+        ));
+
+        assert!(matches!(
+            state.handle(Request::Unlock {
+                password: "wrong".to_string()
+            }),
+            Response::Error { .. }
+        ));
+        assert!(matches!(
+            state.handle(Request::Unlock {
+                password: "hunter2".to_string()
+            }),
+            Response::Ok
+        ));
+        // Locking wiped the cache
+        assert!(matches!(get(&mut state, "work"), Response::Miss));
+    }
+
+    #[test]
+    fn test_agent_socket_roundtrip() {
+        let temp_dir = tempdir().unwrap();
+        let socket_path = temp_dir.path().join(SOCKET_FILE_NAME);
+
+        let server_path = socket_path.clone();
+        let server = thread::spawn(move || serve(&server_path, Duration::from_secs(60)));
+
+        let client = AgentClient::new(socket_path.clone());
+        for _ in 0..100 {
+            if socket_path.exists() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(&socket_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // Only the socket is left behind by the private bind directory
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+
+        assert_eq!(
+            client.get(SecretKind::ApiKey, "work").unwrap(),
+            Lookup::Miss
+        );
+        client
+            .add(SecretKind::ApiKey, "work", "sk-ant-REDACTED")
+            .unwrap();
+        assert_eq!(
+            client.get(SecretKind::ApiKey, "work").unwrap(),
+            Lookup::Hit("sk-ant-REDACTED".to_string())
+        );
+        assert_eq!(client.list().unwrap().len(), 1);
+
+        client.evict(SecretKind::ApiKey, "work").unwrap();
+        assert!(client.list().unwrap().is_empty());
+
+        client.stop().unwrap();
+        server.join().unwrap().unwrap();
+        assert!(!socket_path.exists());
+    }
+
+    #[test]
+    fn test_agent_serves_others_while_a_client_is_idle() {
+        let temp_dir = tempdir().unwrap();
+        let socket_path = temp_dir.path().join(SOCKET_FILE_NAME);
+
+        let server_path = socket_path.clone();
+        let server = thread::spawn(move || serve(&server_path, Duration::from_secs(60)));
+        for _ in 0..100 {
+            if socket_path.exists() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // Connect and never send a request
+        let _idle = UnixStream::connect(&socket_path).unwrap();
+
+        let client = AgentClient::new(socket_path.clone());
+        assert!(client.list().unwrap().is_empty());
+
+        client.stop().unwrap();
+        server.join().unwrap().unwrap();
+    }
+}
@@ -9,17 +9,22 @@ use std::path::{Path, PathBuf};
 const CACHE_FILE_NAME: &str = "cache.json";
 const DEFAULT_TTL_SECONDS: i64 = 3600; // 1 hour
 
-#[derive(Debug, Serialize, Deserialize)]
-struct CacheEntry {
-    profile: String,
-    cached_at: DateTime<Utc>,
+/// Profile detected for a directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub profile: String,
+    /// `.claude-profile` file the profile was read from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile_file: Option<PathBuf>,
+    pub cached_at: DateTime<Utc>,
     ttl_seconds: i64,
 }
 
 impl CacheEntry {
-    fn new(profile: String, ttl_seconds: i64) -> Self {
+    fn new(profile: String, profile_file: Option<PathBuf>, ttl_seconds: i64) -> Self {
         Self {
             profile,
+            profile_file,
             cached_at: Utc::now(),
             ttl_seconds,
         }
@@ -71,28 +76,41 @@ impl Cache {
         Ok(())
     }
 
-    fn get(&self, dir: &Path) -> Option<String> {
+    fn get(&self, dir: &Path) -> Option<&CacheEntry> {
         let key = path_to_key(dir);
-        self.entries
-            .get(&key)
-            .filter(|entry| !entry.is_expired())
-            .map(|entry| entry.profile.clone())
+        self.entries.get(&key).filter(|entry| !entry.is_expired())
     }
 
-    fn set(&mut self, dir: &Path, profile: &str) {
+    fn set(&mut self, dir: &Path, profile: &str, profile_file: Option<&Path>) {
         let key = path_to_key(dir);
-        let entry = CacheEntry::new(profile.to_string(), DEFAULT_TTL_SECONDS);
+        let entry = CacheEntry::new(
+            profile.to_string(),
+            profile_file.map(Path::to_path_buf),
+            DEFAULT_TTL_SECONDS,
+        );
         self.entries.insert(key, entry);
     }
 
+    /// Point entries for `old` at `new`; returns how many changed
+    fn rename_profile(&mut self, old: &str, new: &str) -> usize {
+        let mut renamed = 0;
+        for entry in self.entries.values_mut() {
+            if entry.profile == old {
+                entry.profile = new.to_string();
+                renamed += 1;
+            }
+        }
+        renamed
+    }
+
     fn clear_expired(&mut self) {
         self.entries.retain(|_, entry| !entry.is_expired());
     }
 }
 
 fn get_cache_path() -> Result<PathBuf> {
-    let vault_dir = config::get_vault_dir()?;
-    Ok(vault_dir.join(CACHE_FILE_NAME))
+    let cache_dir = config::get_cache_dir()?;
+    Ok(cache_dir.join(CACHE_FILE_NAME))
 }
 
 fn path_to_key(path: &Path) -> String {
@@ -100,22 +118,32 @@ fn path_to_key(path: &Path) -> String {
 }
 
 /// Get cached profile for directory
-pub fn get(dir: &Path) -> Result<Option<String>> {
+pub fn get(dir: &Path) -> Result<Option<CacheEntry>> {
     let mut cache = Cache::load()?;
     cache.clear_expired();
-    Ok(cache.get(dir))
+    Ok(cache.get(dir).cloned())
 }
 
-/// Set cached profile for directory
-pub fn set(dir: &Path, profile: &str) -> Result<()> {
+/// Set cached profile for directory, and the `.claude-profile` it came from
+pub fn set(dir: &Path, profile: &str, profile_file: Option<&Path>) -> Result<()> {
     let mut cache = Cache::load()?;
-    cache.set(dir, profile);
+    cache.set(dir, profile, profile_file);
     cache.clear_expired();
     cache.save()?;
     Ok(())
 }
 
+/// Rewrite cached detections of a renamed profile
+pub fn rename_profile(old: &str, new: &str) -> Result<()> {
+    let mut cache = Cache::load()?;
+    if cache.rename_profile(old, new) > 0 {
+        cache.save()?;
+    }
+    Ok(())
+}
+
 /// Clear all cache entries
+#[allow(dead_code)] // no command clears the whole cache yet
 pub fn clear() -> Result<()> {
     let cache_path = get_cache_path()?;
     if cache_path.exists() {
@@ -127,14 +155,13 @@ pub fn clear() -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tempfile::tempdir;
 
     #[test]
     fn test_cache_entry_expiration() {
-        let entry = CacheEntry::new("test".to_string(), -1); // Already expired
+        let entry = CacheEntry::new("test".to_string(), None, -1); // Already expired
         assert!(entry.is_expired());
 
-        let entry = CacheEntry::new("test".to_string(), 3600); // Not expired
+        let entry = CacheEntry::new("test".to_string(), None, 3600); // Not expired
         assert!(!entry.is_expired());
     }
 
@@ -143,10 +170,10 @@ mod tests {
         let mut cache = Cache::new();
         let test_path = Path::new("/tmp/test");
 
-        cache.set(test_path, "test-profile");
+        cache.set(test_path, "test-profile", None);
 
-        let result = cache.get(test_path);
-        assert_eq!(result, Some("test-profile".to_string()));
+        let result = cache.get(test_path).map(|entry| entry.profile.as_str());
+        assert_eq!(result, Some("test-profile"));
     }
 
     #[test]
@@ -156,7 +183,7 @@ mod tests {
 
         // Add expired entry manually
         let key = path_to_key(test_path);
-        let mut entry = CacheEntry::new("test".to_string(), 0);
+        let mut entry = CacheEntry::new("test".to_string(), None, 0);
         entry.cached_at = Utc::now() - Duration::seconds(10);
         cache.entries.insert(key, entry);
 
@@ -166,6 +193,19 @@ mod tests {
         assert!(cache.get(test_path).is_none());
     }
 
+    #[test]
+    fn test_cache_rename_profile() {
+        let mut cache = Cache::new();
+        cache.set(Path::new("/tmp/a"), "work", None);
+        cache.set(Path::new("/tmp/b"), "personal", None);
+
+        assert_eq!(cache.rename_profile("work", "acme"), 1);
+        let profile = |dir: &str| cache.get(Path::new(dir)).map(|entry| entry.profile.clone());
+        assert_eq!(profile("/tmp/a"), Some("acme".to_string()));
+        assert_eq!(profile("/tmp/b"), Some("personal".to_string()));
+        assert_eq!(cache.rename_profile("work", "acme"), 0);
+    }
+
     #[test]
     fn test_path_to_key() {
         let path = Path::new("/tmp/test/dir");
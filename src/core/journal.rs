@@ -0,0 +1,143 @@
+use crate::core::claude_code::CredentialStorage;
+use crate::core::config;
+use crate::error::{Error, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+const JOURNAL_FILE_NAME: &str = "claude-code-switch.json";
+const FORMAT_VERSION: u32 = 1;
+
+/// Record of an in-progress Claude Code credential switch
+///
+/// Written (0600) before Claude Code's credentials are replaced and removed once
+/// they are restored, so a run that dies in between can be undone later.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SwitchJournal {
+    pub version: u32,
+    /// Profile Claude Code was switched to
+    pub profile: String,
+    /// claude-vault process that performed the switch
+    pub pid: u32,
+    pub started_at: DateTime<Utc>,
+    /// Where the switched credentials live
+    #[serde(default)]
+    pub storage: CredentialStorage,
+    /// Claude Code credentials before the switch; None if there were none
+    pub backup: Option<String>,
+}
+
+impl SwitchJournal {
+    pub fn new(profile: &str, storage: CredentialStorage, backup: Option<String>) -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            profile: profile.to_string(),
+            pid: std::process::id(),
+            started_at: Utc::now(),
+            storage,
+            backup,
+        }
+    }
+}
+
+/// Location of the switch journal in the state directory
+pub fn journal_path() -> Result<PathBuf> {
+    Ok(config::get_state_dir()?.join(JOURNAL_FILE_NAME))
+}
+
+/// Read the journal at `path`, if one exists
+pub fn read(path: &Path) -> Result<Option<SwitchJournal>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let journal: SwitchJournal = serde_json::from_str(&contents).map_err(|e| {
+        Error::ConfigError(format!("Corrupt switch journal {}: {}", path.display(), e))
+    })?;
+
+    if journal.version > FORMAT_VERSION {
+        return Err(Error::ConfigError(format!(
+            "Switch journal {} was written by a newer claude-vault (version {})",
+            path.display(),
+            journal.version
+        )));
+    }
+
+    Ok(Some(journal))
+}
+
+/// Durably write the journal to `path`
+pub fn write(path: &Path, journal: &SwitchJournal) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // Write atomically via a temp file that is private before any token is in it
+    let temp_path = path.with_extension("tmp");
+    let mut file = config::create_private_file(&temp_path)?;
+    file.write_all(serde_json::to_string_pretty(journal)?.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)?;
+
+    Ok(())
+}
+
+/// Remove the journal at `path`; a missing journal is not an error
+pub fn remove(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_journal_roundtrip() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join(JOURNAL_FILE_NAME);
+
+        assert!(read(&path).unwrap().is_none());
+
+        let journal = SwitchJournal::new(
+            "work",
+            CredentialStorage::File {
+                path: temp_dir.path().join(".credentials.json"),
+            },
+            Some("{\"claudeAiOauth\":{}}".to_string()),
+        );
+        write(&path, &journal).unwrap();
+        assert_eq!(read(&path).unwrap(), Some(journal));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        remove(&path).unwrap();
+        assert!(read(&path).unwrap().is_none());
+        remove(&path).unwrap();
+    }
+
+    #[test]
+    fn test_journal_without_backup() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join(JOURNAL_FILE_NAME);
+
+        write(&path, &SwitchJournal::new("personal", CredentialStorage::Keychain, None)).unwrap();
+        let journal = read(&path).unwrap().unwrap();
+        assert_eq!(journal.profile, "personal");
+        assert_eq!(journal.pid, std::process::id());
+        assert!(journal.backup.is_none());
+    }
+}
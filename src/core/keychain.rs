@@ -1,63 +1,37 @@
+#[cfg(unix)]
+use crate::core::agent::{AgentClient, Lookup};
+use crate::core::store::{self, SecretKind};
 use crate::error::{Error, Result};
 use crate::types::CredentialType;
-use keyring::Entry;
 
-const SERVICE_NAME: &str = "claude-vault";
-const OAUTH_SERVICE_NAME: &str = "claude-vault-oauth";
-const REFRESH_TOKEN_SERVICE_NAME: &str = "claude-vault-oauth-refresh";
-
-/// Store credential in system keychain
+/// Store credential in the configured credential store
 pub fn store(profile: &str, credential: &str) -> Result<()> {
     validate_api_key(credential)?;
 
-    let entry = Entry::new(SERVICE_NAME, profile)
-        .map_err(|e| Error::KeychainError(e.to_string()))?;
-
-    entry
-        .set_password(credential)
-        .map_err(|e| Error::KeychainError(e.to_string()))?;
-
-    Ok(())
+    put(SecretKind::ApiKey, profile, credential)
 }
 
-/// Store OAuth token in system keychain
+/// Store OAuth token in the configured credential store
 pub fn store_oauth(profile: &str, token: &str) -> Result<()> {
     if token.is_empty() {
         return Err(Error::ConfigError("OAuth token cannot be empty".to_string()));
     }
 
-    let entry = Entry::new(OAUTH_SERVICE_NAME, profile)
-        .map_err(|e| Error::KeychainError(e.to_string()))?;
-
-    entry
-        .set_password(token)
-        .map_err(|e| Error::KeychainError(e.to_string()))?;
-
-    Ok(())
+    put(SecretKind::OAuthToken, profile, token)
 }
 
-/// Retrieve credential from system keychain (API key)
+/// Retrieve credential from the configured credential store (API key)
 pub fn get(profile: &str) -> Result<String> {
-    let entry = Entry::new(SERVICE_NAME, profile)
-        .map_err(|e| Error::KeychainError(e.to_string()))?;
-
-    let key = entry.get_password().map_err(|e| {
-        Error::KeychainError(format!("Failed to get key for profile '{}': {}", profile, e))
-    })?;
+    let key = fetch(SecretKind::ApiKey, profile)?;
 
     validate_api_key(&key)?;
 
     Ok(key)
 }
 
-/// Retrieve OAuth token from system keychain
+/// Retrieve OAuth token from the configured credential store
 pub fn get_oauth(profile: &str) -> Result<String> {
-    let entry = Entry::new(OAUTH_SERVICE_NAME, profile)
-        .map_err(|e| Error::KeychainError(e.to_string()))?;
-
-    let token = entry.get_password().map_err(|e| {
-        Error::KeychainError(format!("Failed to get OAuth token for profile '{}': {}", profile, e))
-    })?;
+    let token = fetch(SecretKind::OAuthToken, profile)?;
 
     if token.is_empty() {
         return Err(Error::KeychainError("OAuth token is empty".to_string()));
@@ -74,28 +48,14 @@ pub fn get_by_type(profile: &str, cred_type: CredentialType) -> Result<String> {
     }
 }
 
-/// Delete API key from system keychain
+/// Delete API key from the configured credential store
 pub fn delete(profile: &str) -> Result<()> {
-    let entry = Entry::new(SERVICE_NAME, profile)
-        .map_err(|e| Error::KeychainError(e.to_string()))?;
-
-    entry
-        .delete_password()
-        .map_err(|e| Error::KeychainError(e.to_string()))?;
-
-    Ok(())
+    remove(SecretKind::ApiKey, profile)
 }
 
-/// Delete OAuth token from system keychain
+/// Delete OAuth token from the configured credential store
 pub fn delete_oauth(profile: &str) -> Result<()> {
-    let entry = Entry::new(OAUTH_SERVICE_NAME, profile)
-        .map_err(|e| Error::KeychainError(e.to_string()))?;
-
-    entry
-        .delete_password()
-        .map_err(|e| Error::KeychainError(e.to_string()))?;
-
-    Ok(())
+    remove(SecretKind::OAuthToken, profile)
 }
 
 /// Delete credential based on type
@@ -111,30 +71,18 @@ pub fn delete_by_type(profile: &str, cred_type: CredentialType) -> Result<()> {
     }
 }
 
-/// Store refresh token in system keychain
+/// Store refresh token in the configured credential store
 pub fn store_refresh_token(profile: &str, token: &str) -> Result<()> {
     if token.is_empty() {
         return Err(Error::ConfigError("Refresh token cannot be empty".to_string()));
     }
 
-    let entry = Entry::new(REFRESH_TOKEN_SERVICE_NAME, profile)
-        .map_err(|e| Error::KeychainError(e.to_string()))?;
-
-    entry
-        .set_password(token)
-        .map_err(|e| Error::KeychainError(e.to_string()))?;
-
-    Ok(())
+    put(SecretKind::RefreshToken, profile, token)
 }
 
-/// Retrieve refresh token from system keychain
+/// Retrieve refresh token from the configured credential store
 pub fn get_refresh_token(profile: &str) -> Result<String> {
-    let entry = Entry::new(REFRESH_TOKEN_SERVICE_NAME, profile)
-        .map_err(|e| Error::KeychainError(e.to_string()))?;
-
-    let token = entry.get_password().map_err(|e| {
-        Error::KeychainError(format!("Failed to get refresh token for profile '{}': {}", profile, e))
-    })?;
+    let token = fetch(SecretKind::RefreshToken, profile)?;
 
     if token.is_empty() {
         return Err(Error::KeychainError("Refresh token is empty".to_string()));
@@ -143,14 +91,107 @@ pub fn get_refresh_token(profile: &str) -> Result<String> {
     Ok(token)
 }
 
-/// Delete refresh token from system keychain
+/// Delete refresh token from the configured credential store
 pub fn delete_refresh_token(profile: &str) -> Result<()> {
-    let entry = Entry::new(REFRESH_TOKEN_SERVICE_NAME, profile)
-        .map_err(|e| Error::KeychainError(e.to_string()))?;
+    remove(SecretKind::RefreshToken, profile)
+}
+
+/// Fetch a secret, consulting the credential agent first when one is running
+fn fetch(kind: SecretKind, profile: &str) -> Result<String> {
+    #[cfg(unix)]
+    if let Some(agent) = AgentClient::from_env() {
+        match agent.get(kind, profile) {
+            Ok(Lookup::Hit(secret)) => return Ok(secret),
+            Ok(Lookup::Locked) => {
+                return Err(Error::AgentError(
+                    "Credential agent is locked; run 'claude-vault agent unlock'".to_string(),
+                ))
+            }
+            // A miss or an unreachable agent falls back to the store
+            Ok(Lookup::Miss) | Err(_) => {}
+        }
+
+        let secret = store::open()?.get(kind, profile)?;
+        let _ = agent.add(kind, profile, &secret);
+        return Ok(secret);
+    }
+
+    store::open()?.get(kind, profile)
+}
+
+/// Store a secret, keeping a running agent's cache in sync
+///
+/// The agent's copy is evicted before the store changes, and the write fails
+/// if that isn't possible, so the agent never serves the old value afterwards.
+fn put(kind: SecretKind, profile: &str, secret: &str) -> Result<()> {
+    #[cfg(unix)]
+    let agent = AgentClient::from_env();
+    #[cfg(unix)]
+    if let Some(ref agent) = agent {
+        agent.evict(kind, profile)?;
+    }
+
+    store::open()?.store(kind, profile, secret)?;
+
+    // Caching the new value is only an optimization; a locked agent refuses it
+    #[cfg(unix)]
+    if let Some(ref agent) = agent {
+        let _ = agent.add(kind, profile, secret);
+    }
+
+    Ok(())
+}
+
+/// Delete a secret, evicting it from a running agent first (see `put`)
+fn remove(kind: SecretKind, profile: &str) -> Result<()> {
+    #[cfg(unix)]
+    if let Some(agent) = AgentClient::from_env() {
+        agent.evict(kind, profile)?;
+    }
+
+    store::open()?.delete(kind, profile)
+}
 
-    entry
-        .delete_password()
-        .map_err(|e| Error::KeychainError(e.to_string()))?;
+/// List the kinds of secret stored for a profile (never the secret values)
+///
+/// Only this profile's entries are looked up, not those of every profile.
+pub fn list_stored(profile: &str) -> Result<Vec<SecretKind>> {
+    let store = store::open()?;
+    let mut stored = Vec::new();
+
+    for kind in SecretKind::ALL {
+        if store.contains(kind, profile)? {
+            stored.push(kind);
+        }
+    }
+
+    Ok(stored)
+}
+
+/// Copy every secret stored for `from` to `to`, returning the kinds copied
+///
+/// All or nothing: if one copy fails, those already made are deleted again.
+pub fn copy_all(from: &str, to: &str) -> Result<Vec<SecretKind>> {
+    let mut copied = Vec::new();
+
+    for kind in list_stored(from)? {
+        if let Err(e) = fetch(kind, from).and_then(|secret| put(kind, to, &secret)) {
+            for kind in copied {
+                let _ = remove(kind, to);
+            }
+            return Err(e);
+        }
+        copied.push(kind);
+    }
+
+    Ok(copied)
+}
+
+/// Delete the given kinds of secret stored for a profile
+pub fn delete_kinds(profile: &str, kinds: &[SecretKind]) -> Result<()> {
+    for kind in kinds {
+        remove(*kind, profile)?;
+    }
 
     Ok(())
 }
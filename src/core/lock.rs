@@ -0,0 +1,150 @@
+use crate::core::config;
+use crate::error::Result;
+use fs2::FileExt;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+const LOCKS_DIR_NAME: &str = "locks";
+const CONFIG_LOCK_NAME: &str = "config";
+const CLAUDE_CODE_LOCK_NAME: &str = "claude-code";
+const LEGACY_MIGRATION_LOCK_NAME: &str = "legacy-migration";
+const CREDENTIALS_LOCK_NAME: &str = "credentials";
+
+/// Exclusive advisory lock on a file under the vault directory, released on drop
+pub struct FileLock {
+    file: File,
+}
+
+impl FileLock {
+    /// Block until the lock at `path` is acquired
+    pub fn acquire(path: &Path, waiting_message: &str) -> Result<Self> {
+        let file = open_lock_file(path)?;
+
+        if file.try_lock_exclusive().is_err() {
+            eprintln!("⏳ {}", waiting_message);
+            file.lock_exclusive()?;
+        }
+
+        Ok(Self { file })
+    }
+
+    /// Acquire the lock at `path` only if nobody else holds it
+    pub fn try_acquire(path: &Path) -> Result<Option<Self>> {
+        let file = open_lock_file(path)?;
+
+        match file.try_lock_exclusive() {
+            Ok(()) => Ok(Some(Self { file })),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+fn open_lock_file(path: &Path) -> Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    config::set_file_permissions(path)?;
+
+    Ok(file)
+}
+
+fn lock_path(name: &str) -> Result<PathBuf> {
+    let state_dir = config::get_state_dir()?;
+    Ok(state_dir.join(LOCKS_DIR_NAME).join(format!("{}.lock", name)))
+}
+
+/// Lock serializing OAuth token refreshes for a profile across processes
+pub fn lock_profile(profile_name: &str) -> Result<FileLock> {
+    FileLock::acquire(
+        &lock_path(&format!("profile-{}", profile_name))?,
+        &format!(
+            "Waiting for another claude-vault process to finish refreshing '{}'...",
+            profile_name
+        ),
+    )
+}
+
+/// Lock held while moving files out of the legacy `~/.claude-vault` directory
+pub fn lock_legacy_migration() -> Result<FileLock> {
+    FileLock::acquire(
+        &lock_path(LEGACY_MIGRATION_LOCK_NAME)?,
+        "Waiting for another claude-vault process to finish moving files...",
+    )
+}
+
+/// Lock serializing read-modify-write cycles of config.toml across processes
+pub fn lock_config() -> Result<FileLock> {
+    FileLock::acquire(
+        &lock_path(CONFIG_LOCK_NAME)?,
+        "Waiting for another claude-vault process to finish updating config...",
+    )
+}
+
+/// Path of the lock serializing read-modify-write cycles of the encrypted
+/// credential file across processes
+pub fn credentials_lock_path() -> Result<PathBuf> {
+    lock_path(CREDENTIALS_LOCK_NAME)
+}
+
+/// Lock held while Claude Code's credentials are switched to a profile;
+/// None if another claude-vault process currently holds it
+pub fn try_lock_claude_code() -> Result<Option<FileLock>> {
+    FileLock::try_acquire(&lock_path(CLAUDE_CODE_LOCK_NAME)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_lock_is_exclusive() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("locks").join("test.lock");
+
+        let guard = FileLock::try_acquire(&path).unwrap();
+        assert!(guard.is_some());
+        assert!(FileLock::try_acquire(&path).unwrap().is_none());
+
+        drop(guard);
+        assert!(FileLock::try_acquire(&path).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_lock_waits_for_holder() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("test.lock");
+
+        let guard = FileLock::acquire(&path, "waiting").unwrap();
+        let (tx, rx) = mpsc::channel();
+
+        let waiter_path = path.clone();
+        let waiter = thread::spawn(move || {
+            let _guard = FileLock::acquire(&waiter_path, "waiting").unwrap();
+            tx.send(()).unwrap();
+        });
+
+        // The waiter must not get the lock while we hold it
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+
+        drop(guard);
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        waiter.join().unwrap();
+    }
+}
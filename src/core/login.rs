@@ -0,0 +1,430 @@
+use crate::core::oauth::{self, TokenResponse};
+use crate::core::{config, keychain, ProfileManager};
+use crate::error::{Error, Result};
+use crate::types::{OAuthConfig, OAuthIdentity, OAuthOverrides, Profile};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::RngCore;
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+
+const CALLBACK_PATH: &str = "/callback";
+
+/// PKCE verifier and its S256 challenge (RFC 7636)
+pub struct Pkce {
+    pub verifier: String,
+    pub challenge: String,
+}
+
+impl Pkce {
+    pub fn generate() -> Self {
+        let verifier = random_urlsafe(32);
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        Self {
+            verifier,
+            challenge,
+        }
+    }
+}
+
+/// Random URL-safe string built from `bytes` bytes of entropy
+fn random_urlsafe(bytes: usize) -> String {
+    let mut buffer = vec![0u8; bytes];
+    rand::thread_rng().fill_bytes(&mut buffer);
+    URL_SAFE_NO_PAD.encode(buffer)
+}
+
+/// Build the authorization URL the user opens in a browser
+pub fn authorization_url(
+    oauth: &OAuthConfig,
+    redirect_uri: &str,
+    pkce: &Pkce,
+    state: &str,
+) -> Result<Url> {
+    let scope = oauth.scopes.join(" ");
+    Url::parse_with_params(
+        &oauth.authorize_endpoint,
+        &[
+            ("response_type", "code"),
+            ("client_id", oauth.client_id.as_str()),
+            ("redirect_uri", redirect_uri),
+            ("scope", scope.as_str()),
+            ("code_challenge", pkce.challenge.as_str()),
+            ("code_challenge_method", "S256"),
+            ("state", state),
+        ],
+    )
+    .map_err(|e| {
+        Error::ConfigError(format!(
+            "Invalid authorization endpoint '{}': {}",
+            oauth.authorize_endpoint, e
+        ))
+    })
+}
+
+/// Wait on the loopback listener for the authorization redirect and return the code
+pub fn wait_for_callback(listener: &TcpListener, expected_state: &str) -> Result<String> {
+    loop {
+        let (stream, _) = listener.accept()?;
+
+        match read_callback(&stream, expected_state) {
+            // Browsers also ask for /favicon.ico and the like; keep waiting
+            Ok(Callback::OtherPath) => {
+                respond(&stream, "404 Not Found", "Not found")?;
+            }
+            // A stale tab or a forged request must not end this login
+            Ok(Callback::StateMismatch) => {
+                respond(
+                    &stream,
+                    "400 Bad Request",
+                    "OAuth state mismatch; this redirect is not for the pending login",
+                )?;
+            }
+            Ok(Callback::Code(code)) => {
+                respond(
+                    &stream,
+                    "200 OK",
+                    "Login complete. You can close this window and return to the terminal.",
+                )?;
+                return Ok(code);
+            }
+            Err(e) => {
+                respond(&stream, "400 Bad Request", &format!("Login failed: {}", e))?;
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// What a request to the redirect listener turned out to be
+enum Callback {
+    Code(String),
+    /// Not the callback path
+    OtherPath,
+    /// A redirect whose state isn't the one this login sent
+    StateMismatch,
+}
+
+/// Parse one redirect request
+fn read_callback(stream: &TcpStream, expected_state: &str) -> Result<Callback> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Drain the headers so closing the socket doesn't reset the connection
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    let target = request_line
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| Error::ConfigError("Malformed redirect request".to_string()))?;
+
+    let url = Url::parse(&format!("http://localhost{}", target))
+        .map_err(|e| Error::ConfigError(format!("Malformed redirect request: {}", e)))?;
+
+    if url.path() != CALLBACK_PATH {
+        return Ok(Callback::OtherPath);
+    }
+
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+
+    // Checked first, so only the authorization server can end the login
+    if param("state").as_deref() != Some(expected_state) {
+        return Ok(Callback::StateMismatch);
+    }
+
+    if let Some(error) = param("error") {
+        let description = param("error_description").unwrap_or_default();
+        return Err(Error::ConfigError(format!(
+            "Authorization denied: {} {}",
+            error, description
+        )));
+    }
+
+    param("code")
+        .map(Callback::Code)
+        .ok_or_else(|| Error::ConfigError("Redirect did not include a code".to_string()))
+}
+
+/// Answer the browser in plain text; messages can echo the redirect's
+/// `error` parameters, which must never be rendered as markup
+fn respond(mut stream: &TcpStream, status: &str, message: &str) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\ncontent-type: text/plain; charset=utf-8\r\nx-content-type-options: nosniff\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        status,
+        message.len(),
+        message
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Exchange the authorization code for tokens
+pub(crate) fn exchange_code(
+    oauth: &OAuthConfig,
+    code: &str,
+    redirect_uri: &str,
+    pkce: &Pkce,
+    state: &str,
+) -> Result<TokenResponse> {
+    oauth::request_token(
+        oauth,
+        &[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("code_verifier", &pkce.verifier),
+            ("state", state),
+        ],
+    )
+}
+
+/// Run the authorization-code + PKCE flow and store the tokens in a profile
+///
+/// `overrides` are applied on top of the global `oauth` settings and saved
+/// with the profile so later refreshes use the same endpoint and client.
+/// `open_url` is handed the authorization URL (e.g. to launch a browser).
+pub fn login<F>(
+    profile_name: &str,
+    oauth: &OAuthConfig,
+    overrides: &OAuthOverrides,
+    port: u16,
+    open_url: F,
+) -> Result<Profile>
+where
+    F: FnOnce(&Url),
+{
+    crate::utils::validate_profile_name(profile_name)?;
+
+    let oauth = &oauth.with_overrides(overrides);
+
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let redirect_uri = format!(
+        "http://localhost:{}{}",
+        listener.local_addr()?.port(),
+        CALLBACK_PATH
+    );
+
+    let pkce = Pkce::generate();
+    let state = random_urlsafe(16);
+    let url = authorization_url(oauth, &redirect_uri, &pkce, &state)?;
+
+    open_url(&url);
+
+    let code = wait_for_callback(&listener, &state)?;
+    let tokens = exchange_code(oauth, &code, &redirect_uri, &pkce, &state)?;
+
+    let description = Some(format!(
+        "Logged in via OAuth on {}",
+        chrono::Utc::now().format("%Y-%m-%d")
+    ));
+
+    // Scopes as granted, falling back to those requested
+    let identity = OAuthIdentity {
+        subscription_type: None,
+        scopes: tokens.scopes().or_else(|| Some(oauth.scopes.clone())),
+        account: tokens.account_identity(),
+    };
+
+    let profile = ProfileManager::add_oauth(
+        profile_name,
+        description,
+        &tokens.access_token,
+        tokens.expires_at(),
+        identity,
+    )?;
+
+    if let Some(ref refresh_token) = tokens.refresh_token {
+        keychain::store_refresh_token(profile_name, refresh_token)?;
+    }
+
+    if profile.oauth == *overrides {
+        return Ok(profile);
+    }
+
+    config::update(|config| {
+        let profile = config
+            .find_profile_mut(profile_name)
+            .ok_or_else(|| Error::ProfileNotFound(profile_name.to_string()))?;
+        profile.oauth = overrides.clone();
+        Ok(profile.clone())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+
+    fn test_oauth(token_endpoint: String) -> OAuthConfig {
+        OAuthConfig {
+            authorize_endpoint: "http://127.0.0.1:1/authorize".to_string(),
+            token_endpoint,
+            client_id: "test-client".to_string(),
+            scopes: vec!["user:inference".to_string(), "user:profile".to_string()],
+            extra_params: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_pkce_challenge_is_s256_of_verifier() {
+        let pkce = Pkce::generate();
+        assert_eq!(pkce.verifier.len(), 43);
+        assert_eq!(
+            pkce.challenge,
+            URL_SAFE_NO_PAD.encode(Sha256::digest(pkce.verifier.as_bytes()))
+        );
+        assert_ne!(pkce.verifier, Pkce::generate().verifier);
+    }
+
+    #[test]
+    fn test_authorization_url() {
+        let pkce = Pkce::generate();
+        let oauth = test_oauth("http://127.0.0.1:1/token".to_string());
+        let url =
+            authorization_url(&oauth, "http://localhost:1234/callback", &pkce, "xyz").unwrap();
+
+        let params: std::collections::HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(params["response_type"], "code");
+        assert_eq!(params["client_id"], "test-client");
+        assert_eq!(params["redirect_uri"], "http://localhost:1234/callback");
+        assert_eq!(params["scope"], "user:inference user:profile");
+        assert_eq!(params["code_challenge"], pkce.challenge);
+        assert_eq!(params["code_challenge_method"], "S256");
+        assert_eq!(params["state"], "xyz");
+    }
+
+    #[test]
+    fn test_wait_for_callback() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let browser = thread::spawn(move || {
+            let favicon = reqwest::blocking::get(format!("http://127.0.0.1:{}/favicon.ico", port));
+            assert_eq!(favicon.unwrap().status().as_u16(), 404);
+
+            reqwest::blocking::get(format!(
+                "http://127.0.0.1:{}/callback?code=abc&state=expected",
+                port
+            ))
+            .unwrap()
+            .status()
+            .as_u16()
+        });
+
+        assert_eq!(wait_for_callback(&listener, "expected").unwrap(), "abc");
+        assert_eq!(browser.join().unwrap(), 200);
+    }
+
+    #[test]
+    fn test_wait_for_callback_ignores_state_mismatch() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let browser = thread::spawn(move || {
+            let status = |query: &str| {
+                reqwest::blocking::get(format!("http://127.0.0.1:{}/callback?{}", port, query))
+                    .unwrap()
+                    .status()
+                    .as_u16()
+            };
+            [
+                status("code=forged&state=forged"),
+                status("error=access_denied&state=forged"),
+                status("code=abc&state=expected"),
+            ]
+        });
+
+        assert_eq!(wait_for_callback(&listener, "expected").unwrap(), "abc");
+        assert_eq!(browser.join().unwrap(), [400, 400, 200]);
+    }
+
+    #[test]
+    fn test_wait_for_callback_error_is_not_rendered_as_html() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let browser = thread::spawn(move || {
+            let response = reqwest::blocking::get(format!(
+                "http://127.0.0.1:{}/callback?error=access_denied&error_description=%3Cscript%3Ealert(1)%3C%2Fscript%3E&state=expected",
+                port
+            ))
+            .unwrap();
+            let content_type = response.headers()["content-type"]
+                .to_str()
+                .unwrap()
+                .to_string();
+            (content_type, response.text().unwrap())
+        });
+
+        assert!(wait_for_callback(&listener, "expected").is_err());
+        let (content_type, body) = browser.join().unwrap();
+        assert_eq!(content_type, "text/plain; charset=utf-8");
+        assert!(body.contains("<script>alert(1)</script>"));
+    }
+
+    #[test]
+    fn test_exchange_code_against_stand_in_server() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let token_endpoint = format!("http://{}/v1/oauth/token", server.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            use std::io::Read;
+
+            let (stream, _) = server.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0u8; content_length];
+            reader.read_exact(&mut body).unwrap();
+            tx.send(String::from_utf8(body).unwrap()).unwrap();
+
+            let response = r#"{"access_token":"at","refresh_token":"rt","expires_in":3600}"#;
+            write!(
+                &stream,
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                response.len(),
+                response
+            )
+            .unwrap();
+        });
+
+        let pkce = Pkce::generate();
+        let oauth = test_oauth(token_endpoint);
+        let tokens =
+            exchange_code(&oauth, "abc", "http://localhost:1/callback", &pkce, "xyz").unwrap();
+
+        assert_eq!(tokens.access_token, "at");
+        assert_eq!(tokens.refresh_token.as_deref(), Some("rt"));
+        assert!(tokens.expires_at().is_some());
+
+        let request: serde_json::Value = serde_json::from_str(&rx.recv().unwrap()).unwrap();
+        assert_eq!(request["grant_type"], "authorization_code");
+        assert_eq!(request["code"], "abc");
+        assert_eq!(request["client_id"], "test-client");
+        assert_eq!(request["code_verifier"], pkce.verifier.as_str());
+    }
+}
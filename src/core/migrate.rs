@@ -0,0 +1,226 @@
+use crate::error::{Error, Result};
+use crate::types::CONFIG_VERSION;
+use toml::{Table, Value};
+
+/// Version assumed for config files written before the `version` key existed
+const INITIAL_VERSION: &str = "1.0";
+
+/// One upgrade step of the config.toml schema
+struct Migration {
+    from: &'static str,
+    to: &'static str,
+    description: &'static str,
+    apply: fn(&mut Table),
+}
+
+/// Every schema upgrade, oldest first; each step starts where the previous one ended
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: "1.0",
+        to: "1.1",
+        description: "Store the subscription type of OAuth profiles as a field",
+        apply: subscription_type_field,
+    },
+    Migration {
+        from: "1.1",
+        to: "1.2",
+        description: "Allow profile tags and [[rules]] mapping directories and remotes to profiles",
+        apply: tags_and_rules,
+    },
+];
+
+/// What `migrate` changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Migrated {
+    pub from: String,
+    pub to: String,
+    pub steps: Vec<&'static str>,
+}
+
+/// Upgrade a raw config.toml table to `CONFIG_VERSION` in place
+///
+/// Returns None if it is already current, and an error if it was written by a
+/// newer claude-vault than this one.
+pub fn migrate(table: &mut Table) -> Result<Option<Migrated>> {
+    let from = table
+        .get("version")
+        .and_then(Value::as_str)
+        .unwrap_or(INITIAL_VERSION)
+        .to_string();
+
+    let version = parse_version(&from)
+        .ok_or_else(|| Error::ConfigError(format!("Unrecognized config version '{}'", from)))?;
+    let current = parse_version(CONFIG_VERSION).unwrap_or_default();
+
+    if version > current {
+        return Err(Error::ConfigError(format!(
+            "config.toml was written by a newer claude-vault (config version {}, this version supports up to {}). Please upgrade claude-vault.",
+            from, CONFIG_VERSION
+        )));
+    }
+
+    if version == current {
+        return Ok(None);
+    }
+
+    let mut to = from.clone();
+    let mut steps = Vec::new();
+    while to != CONFIG_VERSION {
+        let migration = MIGRATIONS
+            .iter()
+            .find(|migration| migration.from == to)
+            .ok_or_else(|| {
+                Error::ConfigError(format!("Don't know how to migrate config version {}", to))
+            })?;
+
+        (migration.apply)(table);
+        steps.push(migration.description);
+        to = migration.to.to_string();
+    }
+
+    table.insert("version".to_string(), Value::String(to.clone()));
+
+    Ok(Some(Migrated { from, to, steps }))
+}
+
+/// "major.minor" as a comparable pair
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let (major, minor) = version.split_once('.')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+/// 1.0 -> 1.1: switching used to guess the subscription type from the
+/// description; record that guess once so later edits don't change it
+fn subscription_type_field(table: &mut Table) {
+    let profiles = match table.get_mut("profiles").and_then(Value::as_array_mut) {
+        Some(profiles) => profiles,
+        None => return,
+    };
+
+    for profile in profiles.iter_mut().filter_map(Value::as_table_mut) {
+        let is_oauth = profile.get("credential_type").and_then(Value::as_str) == Some("oauth");
+        if !is_oauth || profile.contains_key("subscription_type") {
+            continue;
+        }
+
+        let description = profile
+            .get("description")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let subscription_type = if description.contains("max") {
+            "max"
+        } else if description.contains("pro") {
+            "pro"
+        } else {
+            continue;
+        };
+
+        profile.insert(
+            "subscription_type".to_string(),
+            Value::String(subscription_type.to_string()),
+        );
+    }
+}
+
+/// 1.1 -> 1.2: `tags` and `[[rules]]` are new and optional, so existing files
+/// need no changes; the bump stops older versions from dropping them on save
+fn tags_and_rules(_table: &mut Table) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(toml: &str) -> Table {
+        toml.parse().unwrap()
+    }
+
+    #[test]
+    fn test_migrations_form_a_chain_to_current() {
+        let mut version = INITIAL_VERSION;
+        for migration in MIGRATIONS {
+            assert_eq!(migration.from, version);
+            assert!(parse_version(migration.to) > parse_version(migration.from));
+            version = migration.to;
+        }
+        assert_eq!(version, CONFIG_VERSION);
+    }
+
+    #[test]
+    fn test_migrate_subscription_type() {
+        let mut table = parse(
+            r#"
+version = "1.0"
+
+[[profiles]]
+name = "work"
+description = "Imported from Claude Code (max) on 2024-01-01"
+credential_type = "oauth"
+created_at = "2024-01-01T00:00:00Z"
+
+[[profiles]]
+name = "other"
+credential_type = "oauth"
+created_at = "2024-01-01T00:00:00Z"
+
+[[profiles]]
+name = "api"
+description = "pro account"
+created_at = "2024-01-01T00:00:00Z"
+"#,
+        );
+
+        let migrated = migrate(&mut table).unwrap().unwrap();
+        assert_eq!(migrated.from, "1.0");
+        assert_eq!(migrated.to, CONFIG_VERSION);
+        assert_eq!(migrated.steps.len(), MIGRATIONS.len());
+
+        let config: crate::types::Config = table.try_into().unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(
+            config.find_profile("work").unwrap().subscription_type.as_deref(),
+            Some("max")
+        );
+        assert!(config.find_profile("other").unwrap().subscription_type.is_none());
+        assert!(config.find_profile("api").unwrap().subscription_type.is_none());
+    }
+
+    #[test]
+    fn test_migrate_1_1_only_bumps_version() {
+        let mut table = parse("version = \"1.1\"\ndefault_profile = \"work\"\nprofiles = []\n");
+        let mut expected = table.clone();
+        expected.insert("version".to_string(), Value::String("1.2".to_string()));
+
+        let migrated = migrate(&mut table).unwrap().unwrap();
+        assert_eq!(migrated.from, "1.1");
+        assert_eq!(table, expected);
+    }
+
+    #[test]
+    fn test_migrate_current_is_noop() {
+        let mut table = parse(&format!("version = \"{}\"\nprofiles = []\n", CONFIG_VERSION));
+        let before = table.clone();
+
+        assert!(migrate(&mut table).unwrap().is_none());
+        assert_eq!(table, before);
+    }
+
+    #[test]
+    fn test_migrate_without_version() {
+        let mut table = parse("profiles = []\n");
+        let migrated = migrate(&mut table).unwrap().unwrap();
+
+        assert_eq!(migrated.from, INITIAL_VERSION);
+        assert_eq!(table["version"].as_str(), Some(CONFIG_VERSION));
+    }
+
+    #[test]
+    fn test_migrate_refuses_newer_version() {
+        let mut table = parse("version = \"99.0\"\nprofiles = []\n");
+        let err = migrate(&mut table).unwrap_err();
+        assert!(err.to_string().contains("newer claude-vault"));
+
+        // Compared numerically, not as strings
+        assert!(parse_version("1.10") > parse_version("1.9"));
+        assert!(migrate(&mut parse("version = \"banana\"\n")).is_err());
+    }
+}
@@ -1,14 +1,29 @@
+#[cfg(unix)]
+pub mod agent;
 pub mod cache;
 pub mod claude_code;
 pub mod config;
 pub mod detector;
+pub mod git;
+pub mod journal;
 pub mod keychain;
+pub mod lock;
+pub mod login;
+pub mod migrate;
 pub mod oauth;
 pub mod profile;
+pub mod proxy;
+pub mod rules;
+pub mod store;
 
 pub use claude_code::with_profile as with_claude_code_profile;
-pub use config::{get_config_path, get_vault_dir, load, save};
-pub use detector::{detect_profile, detect_profile_for_dir, init_profile};
+// Kept for code that imports these paths, though the CLI itself no longer does
+#[allow(unused_imports)]
+pub use config::{get_config_path, load, save};
+pub use detector::init_profile;
+#[allow(unused_imports)]
 pub use keychain::{delete as delete_key, get as get_key, store as store_key};
-pub use oauth::{ensure_token_valid, refresh_oauth_token};
+#[allow(unused_imports)]
+pub use oauth::refresh_oauth_token;
+pub use oauth::{ensure_token_valid, ensure_token_valid_for};
 pub use profile::ProfileManager;
@@ -1,112 +1,310 @@
-use crate::core::{config, keychain, ProfileManager};
+use crate::core::{config, keychain, lock, ProfileManager};
 use crate::error::{Error, Result};
-use crate::types::CredentialType;
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use crate::types::{AccountIdentity, CredentialType, OAuthConfig};
+use crate::utils::format_duration;
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use std::collections::BTreeMap;
 
-const ANTHROPIC_TOKEN_ENDPOINT: &str = "https://api.anthropic.com/v1/oauth/token";
+/// Token endpoint response, shared by refresh and login
+#[derive(Debug, Deserialize)]
+pub(crate) struct TokenResponse {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_in: Option<i64>,
+    /// Space-separated scopes granted
+    pub scope: Option<String>,
+    pub account: Option<TokenAccount>,
+    pub organization: Option<TokenOrganization>,
+}
 
-#[derive(Debug, Serialize)]
-struct RefreshTokenRequest {
-    grant_type: String,
-    refresh_token: String,
+#[derive(Debug, Deserialize)]
+pub(crate) struct TokenAccount {
+    pub uuid: Option<String>,
+    pub email_address: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
-struct RefreshTokenResponse {
-    access_token: String,
-    refresh_token: Option<String>,
-    expires_in: Option<i64>,
+pub(crate) struct TokenOrganization {
+    pub uuid: Option<String>,
+    pub name: Option<String>,
 }
 
-/// Refresh an OAuth token using the refresh token
-pub fn refresh_oauth_token(profile_name: &str) -> Result<()> {
-    // Get refresh token from keychain
-    let refresh_token = keychain::get_refresh_token(profile_name)?;
+impl TokenResponse {
+    /// Absolute expiration time derived from `expires_in`
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        self.expires_in
+            .map(|expires_in| Utc::now() + Duration::seconds(expires_in))
+    }
+
+    /// Granted scopes, if the endpoint reported them
+    pub fn scopes(&self) -> Option<Vec<String>> {
+        self.scope
+            .as_ref()
+            .map(|scope| scope.split_whitespace().map(str::to_string).collect())
+    }
+
+    /// Account and organization the token was issued for, if reported
+    pub fn account_identity(&self) -> Option<AccountIdentity> {
+        if self.account.is_none() && self.organization.is_none() {
+            return None;
+        }
+
+        Some(AccountIdentity {
+            uuid: self
+                .account
+                .as_ref()
+                .and_then(|account| account.uuid.clone()),
+            email: self
+                .account
+                .as_ref()
+                .and_then(|account| account.email_address.clone()),
+            organization_uuid: self
+                .organization
+                .as_ref()
+                .and_then(|organization| organization.uuid.clone()),
+            organization_name: self
+                .organization
+                .as_ref()
+                .and_then(|organization| organization.name.clone()),
+        })
+    }
+}
 
-    // Prepare request
-    let request = RefreshTokenRequest {
-        grant_type: "refresh_token".to_string(),
-        refresh_token: refresh_token.clone(),
-    };
+/// Body of a token request: the configured extra parameters and client ID,
+/// with the grant-specific `params` taking precedence
+fn token_request_body(oauth: &OAuthConfig, params: &[(&str, &str)]) -> BTreeMap<String, String> {
+    let mut body = oauth.extra_params.clone();
+    body.insert("client_id".to_string(), oauth.client_id.clone());
+    for (key, value) in params {
+        body.insert(key.to_string(), value.to_string());
+    }
+    body
+}
 
-    // Call Anthropic token endpoint
+/// POST a token request to the configured token endpoint
+pub(crate) fn request_token(oauth: &OAuthConfig, params: &[(&str, &str)]) -> Result<TokenResponse> {
     let client = reqwest::blocking::Client::new();
     let response = client
-        .post(ANTHROPIC_TOKEN_ENDPOINT)
-        .json(&request)
+        .post(&oauth.token_endpoint)
+        .json(&token_request_body(oauth, params))
         .send()
-        .map_err(|e| Error::ConfigError(format!("Failed to refresh token: {}", e)))?;
+        .map_err(|e| {
+            Error::TokenRequestFailed(format!(
+                "Failed to reach token endpoint {}: {}",
+                oauth.token_endpoint, e
+            ))
+        })?;
 
     if !response.status().is_success() {
         let status = response.status();
         let body = response
             .text()
             .unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(Error::ConfigError(format!(
-            "Token refresh failed ({}): {}",
-            status, body
-        )));
+        return Err(Error::TokenRequestFailed(format!("{}: {}", status, body)));
     }
 
-    let token_response: RefreshTokenResponse = response
+    response
         .json()
-        .map_err(|e| Error::ConfigError(format!("Failed to parse refresh response: {}", e)))?;
+        .map_err(|e| Error::TokenRequestFailed(format!("Failed to parse token response: {}", e)))
+}
+
+/// Refresh an OAuth token using the refresh token
+///
+/// Callers must hold `lock::lock_profile` so rotating refresh tokens are
+/// never used twice by concurrent processes.
+pub fn refresh_oauth_token(profile_name: &str) -> Result<()> {
+    let config = config::load()?;
+    let profile = config
+        .find_profile(profile_name)
+        .ok_or_else(|| Error::ProfileNotFound(profile_name.to_string()))?;
+    let oauth = config.oauth_for(profile);
+
+    // Get refresh token from keychain
+    let refresh_token = keychain::get_refresh_token(profile_name)?;
+    let scope = oauth.scopes.join(" ");
+
+    let token_response = request_token(
+        &oauth,
+        &[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token.as_str()),
+            ("scope", scope.as_str()),
+        ],
+    )?;
 
     // Calculate new expiration time
-    let expires_at = if let Some(expires_in) = token_response.expires_in {
-        let now = Utc::now();
-        Some(now + chrono::Duration::seconds(expires_in))
-    } else {
-        None
-    };
-
-    // Update profile with new tokens
-    let mut config = config::load()?;
-    if let Some(profile) = config.find_profile_mut(profile_name) {
-        profile.expires_at = expires_at;
-        profile.touch();
-    }
-    config::save(&config)?;
+    let expires_at = token_response.expires_at();
+    let scopes = token_response.scopes();
+    let account = token_response.account_identity();
 
-    // Store new access token
+    // Store the rotated tokens before the new expiry, so a failure part way
+    // never records an expiry the stored access token doesn't have. The old
+    // refresh token is already spent, so it is replaced first.
+    if let Some(new_refresh_token) = &token_response.refresh_token {
+        keychain::store_refresh_token(profile_name, new_refresh_token)?;
+    }
     keychain::store_oauth(profile_name, &token_response.access_token)?;
 
-    // Store new refresh token if provided
-    if let Some(new_refresh_token) = token_response.refresh_token {
-        keychain::store_refresh_token(profile_name, &new_refresh_token)?;
-    }
+    // Update profile metadata for the new tokens
+    config::update(|config| {
+        if let Some(profile) = config.find_profile_mut(profile_name) {
+            profile.expires_at = expires_at;
+            if scopes.is_some() {
+                profile.scopes = scopes;
+            }
+            if account.is_some() {
+                profile.account = account;
+            }
+            profile.touch();
+        }
+        Ok(())
+    })?;
 
     Ok(())
 }
 
-/// Check if token is expired and refresh if needed
+/// Check if token is expired or inside its refresh window and refresh if needed
 pub fn ensure_token_valid(profile_name: &str) -> Result<()> {
-    let profile = ProfileManager::get(profile_name)?;
+    ensure_token_valid_for(profile_name, None).map(|_| ())
+}
+
+/// Refresh the token unless it stays valid beyond the profile's refresh window
+/// (or `min_validity`, if longer). Returns the expiry the caller can rely on.
+pub fn ensure_token_valid_for(
+    profile_name: &str,
+    min_validity: Option<Duration>,
+) -> Result<Option<DateTime<Utc>>> {
+    let config = config::load()?;
+    let profile = config
+        .find_profile(profile_name)
+        .cloned()
+        .ok_or_else(|| Error::ProfileNotFound(profile_name.to_string()))?;
 
     // Only handle OAuth profiles
     if profile.credential_type != CredentialType::OAuth {
-        return Ok(());
+        return Ok(None);
+    }
+
+    let mut window = profile.refresh_window(&config.expiry);
+    if let Some(min_validity) = min_validity {
+        window = window.max(min_validity);
+    }
+
+    // Check if token is still valid long enough
+    if !profile.expires_within(window) {
+        return Ok(profile.expires_at);
     }
 
-    // Check if token is expired
-    if !profile.is_expired() {
-        return Ok(());
+    // Only one process refreshes; the others wait here and then pick up its token
+    let _lock = lock::lock_profile(profile_name)?;
+
+    let profile = ProfileManager::get(profile_name)?;
+    if !profile.expires_within(window) {
+        return Ok(profile.expires_at);
     }
 
     // Try to refresh the token
-    eprintln!("🔄 Token expired. Attempting automatic refresh...");
-    match refresh_oauth_token(profile_name) {
-        Ok(()) => {
-            eprintln!("✓ Token refreshed successfully");
-            Ok(())
+    match profile.remaining_validity() {
+        Some(remaining) if remaining > Duration::zero() => eprintln!(
+            "🔄 Token expires in {}. Refreshing ahead of expiry...",
+            format_duration(remaining)
+        ),
+        _ => eprintln!("🔄 Token expired. Attempting automatic refresh..."),
+    }
+
+    if let Err(e) = refresh_oauth_token(profile_name) {
+        // A token that is merely close to expiry is still usable
+        if !profile.is_expired() && min_validity.is_none() {
+            eprintln!("⚠️  Failed to refresh token ahead of expiry: {}", e);
+            eprintln!("   Continuing with the current token until it expires");
+            return Ok(profile.expires_at);
         }
-        Err(e) => {
-            eprintln!("✗ Failed to refresh token: {}", e);
-            eprintln!("   Please re-login to Claude Code and re-import:");
-            eprintln!("   claude /login");
-            eprintln!("   claude-vault import oauth --profile {}", profile_name);
-            Err(Error::ConfigError("Token refresh failed".to_string()))
+
+        eprintln!("✗ Failed to refresh token. Please re-login to Claude Code and re-import:");
+        eprintln!("   claude /login");
+        eprintln!("   claude-vault import oauth --profile {}", profile_name);
+        return Err(Error::TokenRefreshFailed {
+            profile: profile_name.to_string(),
+            reason: e.to_string(),
+        });
+    }
+
+    eprintln!("✓ Token refreshed successfully");
+
+    let refreshed = ProfileManager::get(profile_name)?;
+    if let Some(min_validity) = min_validity {
+        if refreshed.expires_within(min_validity) {
+            return Err(Error::TokenRefreshFailed {
+                profile: profile_name.to_string(),
+                reason: format!(
+                    "token is only valid for {}, less than the requested {}",
+                    refreshed
+                        .remaining_validity()
+                        .map(format_duration)
+                        .unwrap_or_default(),
+                    format_duration(min_validity)
+                ),
+            });
         }
     }
+
+    Ok(refreshed.expires_at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_response_identity() {
+        let response: TokenResponse = serde_json::from_str(
+            r#"{
+                "access_token": "at",
+                "expires_in": 3600,
+                "scope": "user:inference user:profile",
+                "account": {"uuid": "acct-1", "email_address": "dev@example.com"},
+                "organization": {"uuid": "org-1", "name": "Example"}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            response.scopes().unwrap(),
+            vec!["user:inference".to_string(), "user:profile".to_string()]
+        );
+        let account = response.account_identity().unwrap();
+        assert_eq!(account.uuid.as_deref(), Some("acct-1"));
+        assert_eq!(account.email.as_deref(), Some("dev@example.com"));
+        assert_eq!(account.organization_name.as_deref(), Some("Example"));
+
+        let bare: TokenResponse = serde_json::from_str(r#"{"access_token": "at"}"#).unwrap();
+        assert!(bare.scopes().is_none());
+        assert!(bare.account_identity().is_none());
+    }
+
+    #[test]
+    fn test_token_request_body() {
+        let mut oauth = OAuthConfig {
+            client_id: "test-client".to_string(),
+            ..OAuthConfig::default()
+        };
+        oauth
+            .extra_params
+            .insert("audience".to_string(), "gateway".to_string());
+        oauth
+            .extra_params
+            .insert("grant_type".to_string(), "ignored".to_string());
+
+        let body = token_request_body(
+            &oauth,
+            &[("grant_type", "refresh_token"), ("refresh_token", "rt")],
+        );
+
+        assert_eq!(body["client_id"], "test-client");
+        assert_eq!(body["audience"], "gateway");
+        assert_eq!(body["grant_type"], "refresh_token");
+        assert_eq!(body["refresh_token"], "rt");
+        assert_eq!(body.len(), 4);
+    }
 }
@@ -1,7 +1,7 @@
-use crate::core::{config, keychain};
+use crate::core::{cache, claude_code, config, keychain, lock};
 use crate::error::Result;
-use crate::types::{CredentialType, Profile};
-use crate::utils::validate_profile_name;
+use crate::types::{CredentialType, OAuthIdentity, Profile, ProfileEdit, ProfileFilter};
+use crate::utils::{validate_profile_name, validate_tag};
 use chrono::{DateTime, Utc};
 
 pub struct ProfileManager;
@@ -11,19 +11,16 @@ impl ProfileManager {
     pub fn add(name: &str, description: Option<String>, api_key: &str) -> Result<Profile> {
         validate_profile_name(name)?;
 
-        let mut config = config::load()?;
+        config::update(|config| {
+            let profile = Profile::new(name.to_string(), description);
 
-        let profile = Profile::new(name.to_string(), description);
-
-        config.add_profile(profile.clone())?;
-
-        // Store API key in keychain
-        keychain::store(name, api_key)?;
+            config.add_profile(profile.clone())?;
 
-        // Save config
-        config::save(&config)?;
+            // Store API key in keychain
+            keychain::store(name, api_key)?;
 
-        Ok(profile)
+            Ok(profile)
+        })
     }
 
     /// Add a new profile with OAuth token (or update if exists)
@@ -32,69 +29,146 @@ impl ProfileManager {
         description: Option<String>,
         oauth_token: &str,
         expires_at: Option<DateTime<Utc>>,
+        identity: OAuthIdentity,
     ) -> Result<Profile> {
         validate_profile_name(name)?;
 
-        let mut config = config::load()?;
-
-        // Check if profile already exists
-        if config.profile_exists(name) {
-            // Update existing profile
-            if let Some(existing) = config.find_profile_mut(name) {
-                existing.description = description;
-                existing.credential_type = CredentialType::OAuth;
-                existing.expires_at = expires_at;
-                existing.touch(); // Update last_used timestamp
+        config::update(|config| {
+            // Check if profile already exists
+            if config.profile_exists(name) {
+                // Update existing profile
+                if let Some(existing) = config.find_profile_mut(name) {
+                    existing.description = description;
+                    existing.credential_type = CredentialType::OAuth;
+                    existing.expires_at = expires_at;
+                    existing.set_oauth_identity(identity);
+                    existing.touch(); // Update last_used timestamp
+                }
+
+                // Store OAuth token in keychain (overwrites existing)
+                keychain::store_oauth(name, oauth_token)?;
+
+                // Return updated profile
+                Ok(config.find_profile(name).unwrap().clone())
+            } else {
+                // Create new profile
+                let mut profile = Profile::new_with_type(
+                    name.to_string(),
+                    description,
+                    CredentialType::OAuth,
+                );
+
+                profile.expires_at = expires_at;
+                profile.set_oauth_identity(identity);
+
+                config.add_profile(profile.clone())?;
+
+                // Store OAuth token in keychain
+                keychain::store_oauth(name, oauth_token)?;
+
+                Ok(profile)
             }
+        })
+    }
 
-            // Store OAuth token in keychain (overwrites existing)
-            keychain::store_oauth(name, oauth_token)?;
+    /// Remove a profile
+    pub fn remove(name: &str) -> Result<()> {
+        config::update(|config| {
+            // Get profile to determine credential type
+            let profile = config
+                .find_profile(name)
+                .ok_or_else(|| crate::error::Error::ProfileNotFound(name.to_string()))?
+                .clone();
+
+            config.remove_profile(name)?;
+
+            // Delete from keychain based on credential type
+            keychain::delete_by_type(name, profile.credential_type)
+        })
+    }
 
-            // Save config
-            config::save(&config)?;
+    /// Rename a profile, moving its stored secrets to the new name
+    pub fn rename(old: &str, new: &str) -> Result<Profile> {
+        validate_profile_name(new)?;
+
+        // Keep a concurrent refresh from rotating tokens while they are moved
+        let _lock = lock::lock_profile(old)?;
+
+        // Secrets are copied and the isolated config dir moved before the config
+        // is saved, and both are undone if that fails, so the profile stays
+        // usable under exactly one name
+        let mut copied = Vec::new();
+        let mut moved_dir = None;
+        let result = config::update(|config| {
+            config.rename_profile(old, new)?;
+            copied = keychain::copy_all(old, new)?;
+
+            let isolated_dir = claude_code::isolated_config_dir(old)?;
+            if isolated_dir.exists() {
+                let new_dir = claude_code::isolated_config_dir(new)?;
+                std::fs::rename(&isolated_dir, &new_dir)?;
+                moved_dir = Some((new_dir, isolated_dir));
+            }
 
-            // Return updated profile
-            Ok(config.find_profile(name).unwrap().clone())
-        } else {
-            // Create new profile
-            let mut profile = Profile::new_with_type(
-                name.to_string(),
-                description,
-                CredentialType::OAuth,
-            );
+            Ok(config.find_profile(new).unwrap().clone())
+        });
+        let profile = match result {
+            Ok(profile) => profile,
+            Err(e) => {
+                if let Some((new_dir, old_dir)) = moved_dir {
+                    let _ = std::fs::rename(new_dir, old_dir);
+                }
+                let _ = keychain::delete_kinds(new, &copied);
+                return Err(e);
+            }
+        };
 
-            profile.expires_at = expires_at;
+        // The rename is committed; leftovers from here on are only worth a warning
+        if let Err(e) = keychain::delete_kinds(old, &copied) {
+            eprintln!(
+                "⚠️  Warning: Could not delete the secrets stored under '{}': {}",
+                old, e
+            );
+        }
+        if let Err(e) = cache::rename_profile(old, new) {
+            eprintln!("⚠️  Warning: Could not update the detection cache: {}", e);
+        }
 
-            config.add_profile(profile.clone())?;
+        Ok(profile)
+    }
 
-            // Store OAuth token in keychain
-            keychain::store_oauth(name, oauth_token)?;
+    /// Create `dest` with a copy of the metadata and secrets of `source`
+    pub fn clone(source: &str, dest: &str, description: Option<String>) -> Result<Profile> {
+        validate_profile_name(dest)?;
 
-            // Save config
-            config::save(&config)?;
+        let _lock = lock::lock_profile(source)?;
 
-            Ok(profile)
-        }
-    }
+        let mut copied = Vec::new();
+        let result = config::update(|config| {
+            let mut profile = config
+                .find_profile(source)
+                .ok_or_else(|| crate::error::Error::ProfileNotFound(source.to_string()))?
+                .clone();
 
-    /// Remove a profile
-    pub fn remove(name: &str) -> Result<()> {
-        let mut config = config::load()?;
-
-        // Get profile to determine credential type
-        let profile = config.find_profile(name)
-            .ok_or_else(|| crate::error::Error::ProfileNotFound(name.to_string()))?
-            .clone();
+            profile.name = dest.to_string();
+            profile.created_at = Utc::now();
+            profile.last_used = None;
+            if description.is_some() {
+                profile.description = description;
+            }
 
-        config.remove_profile(name)?;
+            config.add_profile(profile.clone())?;
+            copied = keychain::copy_all(source, dest)?;
 
-        // Delete from keychain based on credential type
-        keychain::delete_by_type(name, profile.credential_type)?;
+            Ok(profile)
+        });
 
-        // Save config
-        config::save(&config)?;
+        // Don't leave secrets behind for a profile that was never saved
+        if result.is_err() {
+            let _ = keychain::delete_kinds(dest, &copied);
+        }
 
-        Ok(())
+        result
     }
 
     /// List all profiles
@@ -103,6 +177,30 @@ impl ProfileManager {
         Ok(config.profiles.clone())
     }
 
+    /// List the profiles matching a filter
+    pub fn list_filtered(filter: &ProfileFilter) -> Result<Vec<Profile>> {
+        Ok(Self::list()?
+            .into_iter()
+            .filter(|profile| filter.matches(profile))
+            .collect())
+    }
+
+    /// Change a profile's description, metadata and tags
+    pub fn edit(name: &str, edit: &ProfileEdit) -> Result<Profile> {
+        for tag in &edit.tags {
+            validate_tag(tag)?;
+        }
+
+        config::update(|config| {
+            let profile = config
+                .find_profile_mut(name)
+                .ok_or_else(|| crate::error::Error::ProfileNotFound(name.to_string()))?;
+
+            edit.apply(profile);
+            Ok(profile.clone())
+        })
+    }
+
     /// Get a specific profile
     pub fn get(name: &str) -> Result<Profile> {
         let config = config::load()?;
@@ -114,20 +212,19 @@ impl ProfileManager {
 
     /// Set default profile
     pub fn set_default(name: &str) -> Result<()> {
-        let mut config = config::load()?;
-
-        // Verify profile exists
-        if !config.profile_exists(name) {
-            return Err(crate::error::Error::ProfileNotFound(name.to_string()));
-        }
-
-        config.default_profile = Some(name.to_string());
-        config::save(&config)?;
+        config::update(|config| {
+            // Verify profile exists
+            if !config.profile_exists(name) {
+                return Err(crate::error::Error::ProfileNotFound(name.to_string()));
+            }
 
-        Ok(())
+            config.default_profile = Some(name.to_string());
+            Ok(())
+        })
     }
 
     /// Get API key for profile
+    #[allow(dead_code)] // callers now go through keychain::get_by_type
     pub fn get_api_key(name: &str) -> Result<String> {
         // Verify profile exists in config
         let config = config::load()?;
@@ -140,16 +237,14 @@ impl ProfileManager {
 
     /// Update last_used timestamp for profile
     pub fn update_last_used(name: &str) -> Result<()> {
-        let mut config = config::load()?;
-
-        // Find and update profile
-        let profile = config
-            .find_profile_mut(name)
-            .ok_or_else(|| crate::error::Error::ProfileNotFound(name.to_string()))?;
-
-        profile.touch();
-
-        config::save(&config)?;
-        Ok(())
+        config::update(|config| {
+            // Find and update profile
+            let profile = config
+                .find_profile_mut(name)
+                .ok_or_else(|| crate::error::Error::ProfileNotFound(name.to_string()))?;
+
+            profile.touch();
+            Ok(())
+        })
     }
 }
@@ -0,0 +1,582 @@
+use crate::core::{ensure_token_valid, keychain};
+use crate::error::{Error, Result};
+use crate::types::CredentialType;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Prefix of session tokens, shaped like an API key so clients accept it
+const SESSION_TOKEN_PREFIX: &str = "sk-ant-claude-vault-";
+
+/// Headers that only apply to a single connection and must not be forwarded
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Headers the proxy replaces with the profile's credential
+const CREDENTIAL_HEADERS: &[&str] = &["x-api-key", "authorization"];
+
+const MAX_HEADER_COUNT: usize = 100;
+
+/// Longest request line plus headers accepted
+const MAX_HEAD_SIZE: u64 = 64 * 1024;
+
+/// Largest request body accepted (the Messages API's own limit is 32 MB)
+const MAX_BODY_SIZE: usize = 32 * 1024 * 1024;
+
+/// How long a client may leave the proxy waiting for the rest of its request
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Supplies the credential to inject, called once per request so refreshed tokens are picked up
+pub type CredentialSource = Box<dyn Fn() -> Result<String> + Send + Sync>;
+
+struct Proxy {
+    upstream: String,
+    /// Key clients must present; anything else is refused
+    session_token: String,
+    credential_type: CredentialType,
+    credential: CredentialSource,
+    client: reqwest::blocking::Client,
+}
+
+struct IncomingRequest {
+    method: String,
+    target: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+/// Bind the proxy's listening socket; only loopback addresses unless
+/// `allow_remote`, since anyone who can connect uses the credential
+pub fn bind(listen: &str, allow_remote: bool) -> Result<TcpListener> {
+    let addrs: Vec<_> = listen
+        .to_socket_addrs()
+        .map_err(|e| Error::ConfigError(format!("Invalid listen address {}: {}", listen, e)))?
+        .collect();
+    if !allow_remote && addrs.iter().any(|addr| !addr.ip().is_loopback()) {
+        return Err(Error::ConfigError(format!(
+            "Refusing to listen on non-loopback address {} (use --allow-remote to expose the proxy)",
+            listen
+        )));
+    }
+
+    TcpListener::bind(&addrs[..])
+        .map_err(|e| Error::ConfigError(format!("Failed to listen on {}: {}", listen, e)))
+}
+
+/// Random key for one proxy session, handed to clients in place of the credential
+pub fn session_token() -> String {
+    let mut buffer = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut buffer);
+    format!("{}{}", SESSION_TOKEN_PREFIX, URL_SAFE_NO_PAD.encode(buffer))
+}
+
+/// Credential source reading a profile's credential, refreshing OAuth tokens as needed
+pub fn profile_credential(profile_name: String, credential_type: CredentialType) -> CredentialSource {
+    Box::new(move || {
+        ensure_token_valid(&profile_name)?;
+        keychain::get_by_type(&profile_name, credential_type)
+    })
+}
+
+/// Forward every request on `listener` that presents `session_token` to
+/// `upstream`, injecting the credential header
+pub fn serve(
+    listener: TcpListener,
+    upstream: &str,
+    session_token: String,
+    credential_type: CredentialType,
+    credential: CredentialSource,
+) -> Result<()> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(None)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .map_err(|e| Error::ConfigError(format!("Failed to create HTTP client: {}", e)))?;
+
+    let proxy = Arc::new(Proxy {
+        upstream: upstream.trim_end_matches('/').to_string(),
+        session_token,
+        credential_type,
+        credential,
+        client,
+    });
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        if stream.set_read_timeout(Some(READ_TIMEOUT)).is_err() {
+            continue;
+        }
+
+        let proxy = Arc::clone(&proxy);
+        thread::spawn(move || {
+            if let Err(e) = proxy.handle_connection(stream) {
+                eprintln!("⚠️  Proxy error: {}", e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+impl Proxy {
+    fn handle_connection(&self, stream: TcpStream) -> Result<()> {
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+
+        let mut request = match read_head(&mut reader) {
+            Ok(Some(request)) => request,
+            Ok(None) => return Ok(()),
+            Err(e) => return write_error(&mut writer, 400, "Bad Request", &e.to_string()),
+        };
+
+        // Check the key before reading (and allocating for) the body
+        if !self.is_authorized(&request.headers) {
+            return write_error(
+                &mut writer,
+                401,
+                "Unauthorized",
+                "Missing or invalid claude-vault proxy key",
+            );
+        }
+
+        let body_length = match body_length(&request.headers) {
+            Ok(length) => length,
+            Err(e) => return write_error(&mut writer, 400, "Bad Request", &e.to_string()),
+        };
+        if body_length > MAX_BODY_SIZE {
+            return write_error(
+                &mut writer,
+                413,
+                "Payload Too Large",
+                &format!("Request bodies are limited to {} bytes", MAX_BODY_SIZE),
+            );
+        }
+        request.body = vec![0u8; body_length];
+        reader.read_exact(&mut request.body)?;
+
+        let credential = match (self.credential)() {
+            Ok(credential) => credential,
+            Err(e) => return write_error(&mut writer, 502, "Bad Gateway", &e.to_string()),
+        };
+
+        match self.forward(request, &credential) {
+            Ok(response) => write_response(&mut writer, response),
+            Err(e) => write_error(&mut writer, 502, "Bad Gateway", &e.to_string()),
+        }
+    }
+
+    /// Whether the request carries the session token as `x-api-key` or bearer token
+    fn is_authorized(&self, headers: &[(String, String)]) -> bool {
+        headers.iter().any(|(name, value)| {
+            let presented = if name.eq_ignore_ascii_case("x-api-key") {
+                value.as_str()
+            } else if name.eq_ignore_ascii_case("authorization") {
+                match value.strip_prefix("Bearer ") {
+                    Some(token) => token,
+                    None => return false,
+                }
+            } else {
+                return false;
+            };
+            constant_time_eq(presented.as_bytes(), self.session_token.as_bytes())
+        })
+    }
+
+    fn forward(
+        &self,
+        request: IncomingRequest,
+        credential: &str,
+    ) -> Result<(bool, reqwest::blocking::Response)> {
+        let method = reqwest::Method::from_bytes(request.method.as_bytes())
+            .map_err(|_| Error::ConfigError(format!("Invalid method '{}'", request.method)))?;
+        let is_head = method == reqwest::Method::HEAD;
+        let url = format!("{}{}", self.upstream, request.target);
+
+        let mut builder = self.client.request(method, &url);
+        for (name, value) in &request.headers {
+            let lower = name.to_ascii_lowercase();
+            if HOP_BY_HOP_HEADERS.contains(&lower.as_str())
+                || CREDENTIAL_HEADERS.contains(&lower.as_str())
+                || lower == "host"
+                || lower == "content-length"
+            {
+                continue;
+            }
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+
+        builder = match self.credential_type {
+            CredentialType::ApiKey => builder.header("x-api-key", credential),
+            CredentialType::OAuth => builder.bearer_auth(credential),
+        };
+
+        let response = builder
+            .body(request.body)
+            .send()
+            .map_err(|e| Error::ConfigError(format!("Upstream request failed: {}", e)))?;
+
+        Ok((is_head, response))
+    }
+}
+
+/// Read the request line and headers of one HTTP/1.1 request, leaving the
+/// body unread; None if the client closed the connection first
+fn read_head(reader: &mut impl BufRead) -> Result<Option<IncomingRequest>> {
+    let mut reader = reader.take(MAX_HEAD_SIZE);
+    let too_large = || Error::ConfigError("Request head too large".to_string());
+
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line)? == 0 {
+        return Ok(None);
+    }
+    if !request_line.ends_with('\n') {
+        return Err(too_large());
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(_version)) if target.starts_with('/') => {
+            (method.to_string(), target.to_string())
+        }
+        _ => {
+            return Err(Error::ConfigError(format!(
+                "Malformed request line '{}'",
+                request_line.trim_end()
+            )))
+        }
+    };
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if !line.ends_with('\n') {
+            return Err(too_large());
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            break;
+        }
+        if headers.len() >= MAX_HEADER_COUNT {
+            return Err(Error::ConfigError("Too many headers".to_string()));
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| Error::ConfigError(format!("Malformed header '{}'", line)))?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    Ok(Some(IncomingRequest {
+        method,
+        target,
+        headers,
+        body: Vec::new(),
+    }))
+}
+
+/// Length of the body announced by `headers`
+fn body_length(headers: &[(String, String)]) -> Result<usize> {
+    let header = |wanted: &str| {
+        headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(wanted))
+            .map(|(_, value)| value.clone())
+    };
+
+    if header("transfer-encoding").is_some() {
+        return Err(Error::ConfigError(
+            "Chunked request bodies are not supported".to_string(),
+        ));
+    }
+
+    match header("content-length") {
+        Some(value) => value
+            .parse::<usize>()
+            .map_err(|_| Error::ConfigError(format!("Invalid Content-Length '{}'", value))),
+        None => Ok(0),
+    }
+}
+
+/// Relay the upstream response, streaming the body chunk by chunk (for SSE)
+fn write_response(
+    writer: &mut impl Write,
+    (is_head, mut response): (bool, reqwest::blocking::Response),
+) -> Result<()> {
+    let status = response.status();
+    let has_body = !is_head && status.as_u16() != 204 && status.as_u16() != 304;
+
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\n",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or("")
+    )?;
+
+    for (name, value) in response.headers() {
+        let lower = name.as_str();
+        if HOP_BY_HOP_HEADERS.contains(&lower) || (has_body && lower == "content-length") {
+            continue;
+        }
+        writer.write_all(name.as_str().as_bytes())?;
+        writer.write_all(b": ")?;
+        writer.write_all(value.as_bytes())?;
+        writer.write_all(b"\r\n")?;
+    }
+
+    if has_body {
+        writer.write_all(b"transfer-encoding: chunked\r\n")?;
+    }
+    writer.write_all(b"connection: close\r\n\r\n")?;
+    writer.flush()?;
+
+    if !has_body {
+        return Ok(());
+    }
+
+    let mut buffer = [0u8; 8192];
+    loop {
+        let read = response.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        write!(writer, "{:x}\r\n", read)?;
+        writer.write_all(&buffer[..read])?;
+        writer.write_all(b"\r\n")?;
+        writer.flush()?;
+    }
+    writer.write_all(b"0\r\n\r\n")?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Compare without returning early, so timing doesn't reveal the token
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn write_error(writer: &mut impl Write, code: u16, reason: &str, message: &str) -> Result<()> {
+    let body = serde_json::json!({
+        "type": "error",
+        "error": {
+            "type": "claude_vault_proxy_error",
+            "message": message,
+        }
+    })
+    .to_string();
+
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        code,
+        reason,
+        body.len(),
+        body
+    )?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    /// Minimal upstream that records the request head and answers with `body`
+    fn mock_upstream(body: &'static str) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = read_head(&mut reader).unwrap().unwrap();
+            request.body = vec![0u8; body_length(&request.headers).unwrap()];
+            reader.read_exact(&mut request.body).unwrap();
+
+            let mut head = format!("{} {}\n", request.method, request.target);
+            for (name, value) in &request.headers {
+                head.push_str(&format!("{}: {}\n", name.to_ascii_lowercase(), value));
+            }
+            head.push_str(&String::from_utf8(request.body).unwrap());
+            tx.send(head).unwrap();
+
+            write!(
+                writer,
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        });
+
+        (url, rx)
+    }
+
+    const SESSION_TOKEN: &str = "sk-ant-REDACTED";
+
+    fn start_proxy(upstream: &str, credential_type: CredentialType, credential: &'static str) -> String {
+        let listener = bind("127.0.0.1:0", false).unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let upstream = upstream.to_string();
+
+        thread::spawn(move || {
+            serve(
+                listener,
+                &upstream,
+                SESSION_TOKEN.to_string(),
+                credential_type,
+                Box::new(move || Ok(credential.to_string())),
+            )
+        });
+
+        url
+    }
+
+    #[test]
+    fn test_proxy_injects_api_key() {
+        let (upstream, seen) = mock_upstream("{\"ok\":true}");
+        let proxy = start_proxy(&upstream, CredentialType::ApiKey, "sk-ant-real-key-1234567890");
+
+        let response = reqwest::blocking::Client::new()
+            .post(format!("{}/v1/messages?beta=true", proxy))
+            .header("x-api-key", SESSION_TOKEN)
+            .header("anthropic-version", "2023-06-01")
+            .body("{\"model\":\"claude\"}")
+            .send()
+            .unwrap();
+
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.text().unwrap(), "{\"ok\":true}");
+
+        let head = seen.recv().unwrap();
+        assert!(head.starts_with("POST /v1/messages?beta=true\n"));
+        assert!(head.contains("x-api-key: sk-ant-real-key-1234567890\n"));
+        assert!(!head.contains(SESSION_TOKEN));
+        assert!(head.contains("anthropic-version: 2023-06-01\n"));
+        assert!(head.ends_with("{\"model\":\"claude\"}"));
+    }
+
+    #[test]
+    fn test_proxy_injects_bearer_token() {
+        let (upstream, seen) = mock_upstream("{}");
+        let proxy = start_proxy(&upstream, CredentialType::OAuth, "oauth-access-token");
+
+        let response = reqwest::blocking::Client::new()
+            .get(format!("{}/v1/models", proxy))
+            .bearer_auth(SESSION_TOKEN)
+            .send()
+            .unwrap();
+
+        assert_eq!(response.status().as_u16(), 200);
+
+        let head = seen.recv().unwrap();
+        assert!(head.contains("authorization: Bearer oauth-access-token\n"));
+        assert!(!head.contains(SESSION_TOKEN));
+        assert!(!head.contains("x-api-key"));
+    }
+
+    #[test]
+    fn test_proxy_reports_unreachable_upstream() {
+        // Reserve a port, then free it so nothing is listening there
+        let upstream = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+        let proxy = start_proxy(&upstream, CredentialType::ApiKey, "sk-ant-real-key-1234567890");
+
+        let response = reqwest::blocking::Client::new()
+            .get(format!("{}/v1/models", proxy))
+            .header("x-api-key", SESSION_TOKEN)
+            .send()
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 502);
+    }
+
+    #[test]
+    fn test_proxy_rejects_requests_without_session_token() {
+        let (upstream, _seen) = mock_upstream("{}");
+        let proxy = start_proxy(&upstream, CredentialType::ApiKey, "sk-ant-real-key-1234567890");
+        let client = reqwest::blocking::Client::new();
+
+        let response = client.get(format!("{}/v1/models", proxy)).send().unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+
+        let response = client
+            .get(format!("{}/v1/models", proxy))
+            .header("x-api-key", "sk-ant-claude-vault-guess")
+            .send()
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    #[test]
+    fn test_proxy_checks_key_and_size_before_reading_body() {
+        let (upstream, _seen) = mock_upstream("{}");
+        let proxy = start_proxy(&upstream, CredentialType::ApiKey, "sk-ant-real-key-1234567890");
+        let addr = proxy.trim_start_matches("http://").to_string();
+
+        let status_line = |extra_header: &str| {
+            let mut stream = TcpStream::connect(&addr).unwrap();
+            write!(
+                stream,
+                "POST /v1/messages HTTP/1.1\r\ncontent-length: 99999999999\r\n{}\r\n",
+                extra_header
+            )
+            .unwrap();
+            let mut status_line = String::new();
+            BufReader::new(stream).read_line(&mut status_line).unwrap();
+            status_line
+        };
+
+        assert!(status_line("").starts_with("HTTP/1.1 401"));
+        assert!(
+            status_line(&format!("x-api-key: {}\r\n", SESSION_TOKEN)).starts_with("HTTP/1.1 413")
+        );
+    }
+
+    #[test]
+    fn test_read_head_limits_size() {
+        let mut oversized = format!("GET / HTTP/1.1\r\nx-filler: {}", "a".repeat(70 * 1024));
+        oversized.push_str("\r\n\r\n");
+        assert!(read_head(&mut oversized.as_bytes()).is_err());
+
+        let request = read_head(&mut "GET /v1/models HTTP/1.1\r\nhost: x\r\n\r\n".as_bytes())
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.target, "/v1/models");
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn test_bind_refuses_non_loopback() {
+        assert!(bind("0.0.0.0:0", false).is_err());
+        assert!(bind("localhost:0", false).is_ok());
+        assert!(bind("0.0.0.0:0", true).is_ok());
+    }
+
+    #[test]
+    fn test_session_token_is_random() {
+        let token = session_token();
+        assert!(token.starts_with(SESSION_TOKEN_PREFIX));
+        assert_ne!(token, session_token());
+    }
+}
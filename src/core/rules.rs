@@ -0,0 +1,292 @@
+use crate::core::{config, git};
+use crate::error::{Error, Result};
+use crate::types::{Rule, RuleTarget};
+use glob::{MatchOptions, Pattern};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+const PATH_MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Hosts treat owner and repository names case-insensitively
+const REMOTE_MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    ..PATH_MATCH_OPTIONS
+};
+
+/// A rule that applies to a directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleMatch<'a> {
+    pub rule: &'a Rule,
+    pub matched: Matched,
+}
+
+/// What a rule's pattern matched
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Matched {
+    /// The directory itself or one of its parents
+    Directory { path: PathBuf },
+    /// A remote of the repository containing the directory
+    Remote { name: String, url: String },
+}
+
+/// Rule applying to `dir`. Path rules come first: the one matching the
+/// nearest of `dir` and its parents. Then remote rules, trying `origin` before
+/// other remotes. Several rules matching the same thing resolve in config order.
+pub fn find_match<'a>(rules: &'a [Rule], dir: &Path) -> Option<RuleMatch<'a>> {
+    let home = dirs::home_dir();
+    let compiled: Vec<(&Rule, Pattern)> = rules
+        .iter()
+        .filter_map(|rule| Some((rule, compile(&rule.target, home.as_deref()).ok()?)))
+        .collect();
+    let (path_rules, remote_rules): (Vec<_>, Vec<_>) = compiled
+        .into_iter()
+        .partition(|(rule, _)| matches!(rule.target, RuleTarget::Path(_)));
+
+    let by_path = dir.ancestors().find_map(|ancestor| {
+        path_rules
+            .iter()
+            .find(|(_, pattern)| pattern.matches_path_with(ancestor, PATH_MATCH_OPTIONS))
+            .map(|(rule, _)| RuleMatch {
+                rule,
+                matched: Matched::Directory {
+                    path: ancestor.to_path_buf(),
+                },
+            })
+    });
+    if by_path.is_some() || remote_rules.is_empty() {
+        return by_path;
+    }
+
+    git::remotes_for_dir(dir).into_iter().find_map(|remote| {
+        let normalized = git::normalize_url(&remote.url)?;
+        let (rule, _) = remote_rules.iter().find(|(_, pattern)| {
+            remote_prefixes(&normalized)
+                .any(|prefix| pattern.matches_with(prefix, REMOTE_MATCH_OPTIONS))
+        })?;
+        Some(RuleMatch {
+            rule,
+            matched: Matched::Remote {
+                name: remote.name,
+                url: remote.url,
+            },
+        })
+    })
+}
+
+/// `host/org/repo`, `host/org` and `host`, so a rule for an organisation
+/// covers its repositories
+fn remote_prefixes(normalized: &str) -> impl Iterator<Item = &str> {
+    std::iter::successors(Some(normalized), |prefix| {
+        prefix.rsplit_once('/').map(|(parent, _)| parent)
+    })
+}
+
+/// Compile a rule's pattern, expanding a leading `~` in paths
+fn compile(target: &RuleTarget, home: Option<&Path>) -> Result<Pattern> {
+    let pattern = match target {
+        RuleTarget::Path(path) => expand_path(path, home)?,
+        RuleTarget::Remote(remote) => {
+            if remote.is_empty() || remote.contains("://") || remote.contains('@') {
+                return Err(Error::ConfigError(format!(
+                    "Rule remote '{}' must be a host and path such as 'github.com/acme'",
+                    remote
+                )));
+            }
+            remote.trim_end_matches('/').to_string()
+        }
+    };
+
+    Pattern::new(&pattern)
+        .map_err(|e| Error::ConfigError(format!("Invalid rule {}: {}", target, e)))
+}
+
+fn expand_path(path: &str, home: Option<&Path>) -> Result<String> {
+    let expanded = match (path.strip_prefix('~'), home) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            format!("{}{}", home.display(), rest)
+        }
+        _ => path.to_string(),
+    };
+
+    if !Path::new(&expanded).is_absolute() {
+        return Err(Error::ConfigError(format!(
+            "Rule path '{}' must be absolute or start with ~/",
+            path
+        )));
+    }
+
+    Ok(expanded)
+}
+
+/// Add a rule mapping `target` to `profile`
+pub fn add(target: RuleTarget, profile: &str) -> Result<Rule> {
+    compile(&target, dirs::home_dir().as_deref())?;
+
+    config::update(|config| {
+        if !config.profile_exists(profile) {
+            return Err(Error::ProfileNotFound(profile.to_string()));
+        }
+        if config.rules.iter().any(|rule| rule.target == target) {
+            return Err(Error::ConfigError(format!(
+                "A rule for {} already exists",
+                target
+            )));
+        }
+
+        let rule = Rule {
+            target,
+            profile: profile.to_string(),
+        };
+        config.rules.push(rule.clone());
+        Ok(rule)
+    })
+}
+
+/// Remove the rule for `target`
+pub fn remove(target: &RuleTarget) -> Result<Rule> {
+    config::update(|config| {
+        let pos = config
+            .rules
+            .iter()
+            .position(|rule| rule.target == *target)
+            .ok_or_else(|| Error::ConfigError(format!("No rule for {}", target)))?;
+
+        Ok(config.rules.remove(pos))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn rule(path: &str, profile: &str) -> Rule {
+        Rule {
+            target: RuleTarget::Path(path.to_string()),
+            profile: profile.to_string(),
+        }
+    }
+
+    fn remote_rule(remote: &str, profile: &str) -> Rule {
+        Rule {
+            target: RuleTarget::Remote(remote.to_string()),
+            profile: profile.to_string(),
+        }
+    }
+
+    fn directory(path: &str) -> Matched {
+        Matched::Directory {
+            path: PathBuf::from(path),
+        }
+    }
+
+    #[test]
+    fn test_compile_expands_home() {
+        let home = Path::new("/home/dev");
+        let path = |path: &str| RuleTarget::Path(path.to_string());
+
+        let pattern = compile(&path("~/work/**"), Some(home)).unwrap();
+        assert!(pattern.matches_path_with(Path::new("/home/dev/work/a/b"), PATH_MATCH_OPTIONS));
+
+        assert!(compile(&path("work/**"), Some(home)).is_err());
+        assert!(compile(&path("~other/work"), Some(home)).is_err());
+        assert!(compile(&path("/src/["), Some(home)).is_err());
+    }
+
+    #[test]
+    fn test_compile_rejects_remote_urls() {
+        let remote = |remote: &str| RuleTarget::Remote(remote.to_string());
+
+        assert!(compile(&remote("github.com/acme/*"), None).is_ok());
+        assert!(compile(&remote("https://github.com/acme"), None).is_err());
+        assert!(compile(&remote("git@github.com:acme"), None).is_err());
+        assert!(compile(&remote(""), None).is_err());
+    }
+
+    #[test]
+    fn test_find_match_prefers_nearest_directory() {
+        let rules = vec![
+            rule("/src/*", "personal"),
+            rule("/src/acme/api", "api"),
+            rule("/work/**", "work"),
+        ];
+
+        let found = find_match(&rules, Path::new("/src/acme/api/handlers")).unwrap();
+        assert_eq!(found.rule.profile, "api");
+        assert_eq!(found.matched, directory("/src/acme/api"));
+
+        let found = find_match(&rules, Path::new("/src/acme/web")).unwrap();
+        assert_eq!(found.rule.profile, "personal");
+        assert_eq!(found.matched, directory("/src/acme"));
+
+        let found = find_match(&rules, Path::new("/work/a/b")).unwrap();
+        assert_eq!(found.rule.profile, "work");
+        assert_eq!(found.matched, directory("/work/a/b"));
+
+        assert!(find_match(&rules, Path::new("/elsewhere")).is_none());
+    }
+
+    #[test]
+    fn test_find_match_uses_config_order_for_ties() {
+        let rules = vec![rule("/src/*", "first"), rule("/src/a*", "second")];
+
+        let found = find_match(&rules, Path::new("/src/app")).unwrap();
+        assert_eq!(found.rule.profile, "first");
+    }
+
+    #[test]
+    fn test_remote_prefixes() {
+        let prefixes: Vec<_> = remote_prefixes("github.com/acme/tool").collect();
+        assert_eq!(
+            prefixes,
+            ["github.com/acme/tool", "github.com/acme", "github.com"]
+        );
+    }
+
+    #[test]
+    fn test_find_match_by_remote() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let repo = temp.path().join("tool");
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        fs::write(
+            repo.join(".git/config"),
+            "[remote \"upstream\"]\n\turl = https://github.com/Acme/tool.git\n\
+             [remote \"origin\"]\n\turl = git@github.com:me/tool.git\n",
+        )
+        .unwrap();
+        let dir = repo.join("src");
+
+        let rules = vec![remote_rule("github.com/acme", "work")];
+        let found = find_match(&rules, &dir).unwrap();
+        assert_eq!(found.rule.profile, "work");
+        assert_eq!(
+            found.matched,
+            Matched::Remote {
+                name: "upstream".to_string(),
+                url: "https://github.com/Acme/tool.git".to_string(),
+            }
+        );
+
+        // origin is tried before other remotes
+        let rules = vec![
+            remote_rule("github.com/acme/*", "work"),
+            remote_rule("github.com/me", "personal"),
+        ];
+        assert_eq!(find_match(&rules, &dir).unwrap().rule.profile, "personal");
+
+        // Path rules win over remote rules
+        let rules = vec![
+            remote_rule("github.com/me", "personal"),
+            rule(&format!("{}/**", temp.path().display()), "scratch"),
+        ];
+        assert_eq!(find_match(&rules, &dir).unwrap().rule.profile, "scratch");
+
+        let rules = vec![remote_rule("gitlab.com/*", "work")];
+        assert!(find_match(&rules, &dir).is_none());
+    }
+}
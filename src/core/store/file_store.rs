@@ -0,0 +1,486 @@
+use super::{CredentialStore, SecretKind};
+use crate::core::config;
+use crate::core::lock::{self, FileLock};
+use crate::error::{Error, Result};
+use crate::types::KdfConfig;
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use dialoguer::Password;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+const CREDENTIALS_FILE_NAME: &str = "credentials.enc";
+const FORMAT_VERSION: u32 = 1;
+const KDF_ALGORITHM: &str = "argon2id";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+const ASSOCIATED_DATA: &[u8] = b"claude-vault credentials v1";
+
+/// Environment variable holding the passphrase for the encrypted credential file
+pub const PASSPHRASE_ENV: &str = "CLAUDE_VAULT_PASSPHRASE";
+
+/// Environment variable naming an open file descriptor to read the passphrase from
+pub const PASSPHRASE_FD_ENV: &str = "CLAUDE_VAULT_PASSPHRASE_FD";
+
+/// Passphrase obtained once per process, so a command touching several secrets prompts once
+static PASSPHRASE: OnceCell<String> = OnceCell::new();
+
+/// Most recently derived key, keyed by passphrase, salt and KDF parameters
+#[allow(clippy::type_complexity)]
+static DERIVED_KEY: Mutex<Option<(String, Vec<u8>, KdfConfig, [u8; KEY_LEN])>> = Mutex::new(None);
+
+/// Decrypted file contents: service name -> profile -> secret
+type Secrets = BTreeMap<String, BTreeMap<String, String>>;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EncryptedFile {
+    version: u32,
+    kdf: KdfHeader,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct KdfHeader {
+    algorithm: String,
+    #[serde(flatten)]
+    params: KdfConfig,
+    salt: String,
+}
+
+/// Credential store keeping every secret in a single passphrase-encrypted file
+/// (Argon2id + ChaCha20-Poly1305), for machines without an OS keychain
+pub struct FileStore {
+    path: PathBuf,
+    lock_path: PathBuf,
+    kdf: KdfConfig,
+    passphrase: Option<String>,
+}
+
+impl FileStore {
+    /// Open the credential file in the vault directory
+    pub fn open(kdf: KdfConfig) -> Result<Self> {
+        Ok(Self {
+            path: get_credentials_path()?,
+            lock_path: lock::credentials_lock_path()?,
+            kdf,
+            passphrase: None,
+        })
+    }
+
+    #[cfg(test)]
+    fn with_passphrase(path: PathBuf, kdf: KdfConfig, passphrase: &str) -> Self {
+        Self {
+            lock_path: path.with_extension("lock"),
+            path,
+            kdf,
+            passphrase: Some(passphrase.to_string()),
+        }
+    }
+
+    fn passphrase(&self, confirm: bool) -> Result<String> {
+        if let Some(ref passphrase) = self.passphrase {
+            return Ok(passphrase.clone());
+        }
+
+        PASSPHRASE
+            .get_or_try_init(|| obtain_passphrase(confirm))
+            .cloned()
+    }
+
+    /// Hold off other processes' read-modify-write cycles until the lock is dropped
+    fn lock(&self) -> Result<FileLock> {
+        FileLock::acquire(
+            &self.lock_path,
+            "Waiting for another claude-vault process to finish updating credentials...",
+        )
+    }
+
+    /// Load and decrypt the file, returning the secrets and the salt in use
+    fn load(&self) -> Result<Option<(Secrets, Vec<u8>)>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        let contents = fs::read_to_string(&self.path)?;
+        let file: EncryptedFile = serde_json::from_str(&contents)?;
+
+        if file.version != FORMAT_VERSION {
+            return Err(Error::KeychainError(format!(
+                "Unsupported credential file version {}",
+                file.version
+            )));
+        }
+
+        if file.kdf.algorithm != KDF_ALGORITHM {
+            return Err(Error::KeychainError(format!(
+                "Unsupported key derivation algorithm '{}'",
+                file.kdf.algorithm
+            )));
+        }
+
+        let salt = decode(&file.kdf.salt)?;
+        let nonce = decode(&file.nonce)?;
+        let ciphertext = decode(&file.ciphertext)?;
+
+        let nonce: [u8; NONCE_LEN] = nonce.as_slice().try_into().map_err(|_| {
+            Error::KeychainError("Corrupted credential file: invalid nonce".to_string())
+        })?;
+
+        let key = derive_key(&self.passphrase(false)?, &salt, file.kdf.params)?;
+        let cipher = ChaCha20Poly1305::new(&Key::from(key));
+
+        let plaintext = cipher
+            .decrypt(
+                &Nonce::from(nonce),
+                Payload {
+                    msg: &ciphertext,
+                    aad: ASSOCIATED_DATA,
+                },
+            )
+            .map_err(|_| {
+                Error::KeychainError(
+                    "Failed to decrypt credential file (wrong passphrase or corrupted file)"
+                        .to_string(),
+                )
+            })?;
+
+        let secrets: Secrets = serde_json::from_slice(&plaintext)?;
+
+        Ok(Some((secrets, salt)))
+    }
+
+    /// Encrypt and write the file atomically with 0600 permissions
+    fn save(&self, secrets: &Secrets, salt: Option<Vec<u8>>) -> Result<()> {
+        let creating = salt.is_none();
+        let salt = salt.unwrap_or_else(|| {
+            let mut salt = vec![0u8; SALT_LEN];
+            OsRng.fill_bytes(&mut salt);
+            salt
+        });
+
+        let key = derive_key(&self.passphrase(creating)?, &salt, self.kdf)?;
+        let cipher = ChaCha20Poly1305::new(&Key::from(key));
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+        let plaintext = serde_json::to_vec(secrets)?;
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &plaintext,
+                    aad: ASSOCIATED_DATA,
+                },
+            )
+            .map_err(|_| Error::KeychainError("Failed to encrypt credential file".to_string()))?;
+
+        let file = EncryptedFile {
+            version: FORMAT_VERSION,
+            kdf: KdfHeader {
+                algorithm: KDF_ALGORITHM.to_string(),
+                params: self.kdf,
+                salt: BASE64.encode(&salt),
+            },
+            nonce: BASE64.encode(nonce),
+            ciphertext: BASE64.encode(ciphertext),
+        };
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Write atomically via a temp file that is created with mode 0600
+        let temp_path = self.path.with_extension("tmp");
+        let mut temp_file = config::create_private_file(&temp_path)?;
+        temp_file.write_all(serde_json::to_string_pretty(&file)?.as_bytes())?;
+        temp_file.sync_all()?;
+        fs::rename(&temp_path, &self.path)?;
+
+        Ok(())
+    }
+
+    fn not_found(kind: SecretKind, profile: &str) -> Error {
+        Error::KeychainError(format!(
+            "Failed to get {} for profile '{}': not found in credential file",
+            kind, profile
+        ))
+    }
+}
+
+impl CredentialStore for FileStore {
+    fn get(&self, kind: SecretKind, profile: &str) -> Result<String> {
+        let (secrets, _) = self
+            .load()?
+            .ok_or_else(|| Self::not_found(kind, profile))?;
+
+        secrets
+            .get(kind.service_name())
+            .and_then(|entries| entries.get(profile))
+            .cloned()
+            .ok_or_else(|| Self::not_found(kind, profile))
+    }
+
+    fn store(&self, kind: SecretKind, profile: &str, secret: &str) -> Result<()> {
+        let _lock = self.lock()?;
+        let (mut secrets, salt) = match self.load()? {
+            Some((secrets, salt)) => (secrets, Some(salt)),
+            None => (Secrets::new(), None),
+        };
+
+        secrets
+            .entry(kind.service_name().to_string())
+            .or_default()
+            .insert(profile.to_string(), secret.to_string());
+
+        self.save(&secrets, salt)
+    }
+
+    fn delete(&self, kind: SecretKind, profile: &str) -> Result<()> {
+        let _lock = self.lock()?;
+        let (mut secrets, salt) = self
+            .load()?
+            .ok_or_else(|| Self::not_found(kind, profile))?;
+
+        let entries = secrets
+            .get_mut(kind.service_name())
+            .ok_or_else(|| Self::not_found(kind, profile))?;
+
+        entries
+            .remove(profile)
+            .ok_or_else(|| Self::not_found(kind, profile))?;
+
+        if entries.is_empty() {
+            secrets.remove(kind.service_name());
+        }
+
+        self.save(&secrets, Some(salt))
+    }
+
+    fn list(&self) -> Result<Vec<(SecretKind, String)>> {
+        let secrets = match self.load()? {
+            Some((secrets, _)) => secrets,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut found = Vec::new();
+        for kind in SecretKind::ALL {
+            if let Some(entries) = secrets.get(kind.service_name()) {
+                found.extend(entries.keys().map(|profile| (kind, profile.clone())));
+            }
+        }
+
+        Ok(found)
+    }
+}
+
+fn get_credentials_path() -> Result<PathBuf> {
+    let config_dir = config::get_config_dir()?;
+    Ok(config_dir.join(CREDENTIALS_FILE_NAME))
+}
+
+fn decode(value: &str) -> Result<Vec<u8>> {
+    BASE64
+        .decode(value)
+        .map_err(|e| Error::KeychainError(format!("Corrupted credential file: {}", e)))
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], kdf: KdfConfig) -> Result<[u8; KEY_LEN]> {
+    let mut cached = DERIVED_KEY.lock().unwrap_or_else(|e| e.into_inner());
+
+    if let Some((ref cached_passphrase, ref cached_salt, cached_kdf, key)) = *cached {
+        if cached_passphrase == passphrase && cached_salt == salt && cached_kdf == kdf {
+            return Ok(key);
+        }
+    }
+
+    let params = Params::new(kdf.memory_kib, kdf.iterations, kdf.parallelism, Some(KEY_LEN))
+        .map_err(|e| Error::ConfigError(format!("Invalid KDF parameters: {}", e)))?;
+
+    let mut key = [0u8; KEY_LEN];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| Error::KeychainError(format!("Key derivation failed: {}", e)))?;
+
+    *cached = Some((passphrase.to_string(), salt.to_vec(), kdf, key));
+
+    Ok(key)
+}
+
+/// Obtain the passphrase from the environment, a file descriptor, or an interactive prompt
+fn obtain_passphrase(confirm: bool) -> Result<String> {
+    let passphrase = if let Ok(passphrase) = env::var(PASSPHRASE_ENV) {
+        passphrase
+    } else if let Ok(fd) = env::var(PASSPHRASE_FD_ENV) {
+        read_passphrase_fd(&fd)?
+    } else {
+        let mut prompt = Password::new().with_prompt("claude-vault passphrase");
+        if confirm {
+            prompt = prompt.with_confirmation("Confirm passphrase", "Passphrases do not match");
+        }
+        prompt
+            .interact()
+            .map_err(|e| Error::ConfigError(format!("Failed to read passphrase: {}", e)))?
+    };
+
+    if passphrase.is_empty() {
+        return Err(Error::ConfigError("Passphrase cannot be empty".to_string()));
+    }
+
+    Ok(passphrase)
+}
+
+/// Read the first line of an inherited file descriptor
+#[cfg(unix)]
+fn read_passphrase_fd(fd: &str) -> Result<String> {
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::io::FromRawFd;
+
+    let fd: i32 = fd.trim().parse().map_err(|_| {
+        Error::ConfigError(format!("{} must be a file descriptor number", PASSPHRASE_FD_ENV))
+    })?;
+
+    // SAFETY: the caller hands us this descriptor for the passphrase; we own it from here on
+    let file = unsafe { fs::File::from_raw_fd(fd) };
+
+    let mut line = String::new();
+    BufReader::new(file).read_line(&mut line)?;
+
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(not(unix))]
+fn read_passphrase_fd(_fd: &str) -> Result<String> {
+    Err(Error::ConfigError(format!(
+        "{} is only supported on Unix",
+        PASSPHRASE_FD_ENV
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    // Cheap parameters so tests don't spend seconds in Argon2
+    const TEST_KDF: KdfConfig = KdfConfig {
+        memory_kib: 8,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    #[test]
+    fn test_file_store_roundtrip() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join(CREDENTIALS_FILE_NAME);
+        let store = FileStore::with_passphrase(path.clone(), TEST_KDF, "correct horse");
+
+        store
+            .store(SecretKind::ApiKey, "work", "sk-ant-REDACTED")
+            .unwrap();
+        store.store(SecretKind::OAuthToken, "personal", "token").unwrap();
+
+        assert_eq!(
+            store.get(SecretKind::ApiKey, "work").unwrap(),
+            "sk-ant-REDACTED"
+        );
+        assert_eq!(
+            store.list().unwrap(),
+            vec![
+                (SecretKind::ApiKey, "work".to_string()),
+                (SecretKind::OAuthToken, "personal".to_string()),
+            ]
+        );
+        assert!(store.contains(SecretKind::ApiKey, "work").unwrap());
+        assert!(!store.contains(SecretKind::ApiKey, "personal").unwrap());
+
+        store.delete(SecretKind::ApiKey, "work").unwrap();
+        assert!(store.get(SecretKind::ApiKey, "work").is_err());
+        assert!(store.delete(SecretKind::ApiKey, "work").is_err());
+    }
+
+    #[test]
+    fn test_file_store_concurrent_stores_keep_every_secret() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join(CREDENTIALS_FILE_NAME);
+
+        let writers: Vec<_> = (0..4)
+            .map(|writer| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let store = FileStore::with_passphrase(path, TEST_KDF, "correct horse");
+                    for i in 0..5 {
+                        store
+                            .store(SecretKind::OAuthToken, &format!("p{}-{}", writer, i), "token")
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let store = FileStore::with_passphrase(path, TEST_KDF, "correct horse");
+        assert_eq!(store.list().unwrap().len(), 20);
+    }
+
+    #[test]
+    fn test_file_store_does_not_leak_plaintext() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join(CREDENTIALS_FILE_NAME);
+        let store = FileStore::with_passphrase(path.clone(), TEST_KDF, "correct horse");
+
+        store
+            .store(SecretKind::RefreshToken, "work", "super-secret-refresh")
+            .unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("super-secret-refresh"));
+        assert!(!contents.contains("work"));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn test_file_store_wrong_passphrase() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join(CREDENTIALS_FILE_NAME);
+
+        FileStore::with_passphrase(path.clone(), TEST_KDF, "correct horse")
+            .store(SecretKind::OAuthToken, "work", "token")
+            .unwrap();
+
+        let store = FileStore::with_passphrase(path, TEST_KDF, "battery staple");
+        assert!(matches!(
+            store.get(SecretKind::OAuthToken, "work"),
+            Err(Error::KeychainError(_))
+        ));
+    }
+
+    #[test]
+    fn test_file_store_missing_file() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join(CREDENTIALS_FILE_NAME);
+        let store = FileStore::with_passphrase(path.clone(), TEST_KDF, "correct horse");
+
+        assert!(store.get(SecretKind::ApiKey, "work").is_err());
+        assert!(store.list().unwrap().is_empty());
+        assert!(!path.exists());
+    }
+}
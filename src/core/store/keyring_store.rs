@@ -0,0 +1,66 @@
+use super::{CredentialStore, SecretKind};
+use crate::core::config;
+use crate::error::{Error, Result};
+use keyring::Entry;
+
+/// Credential store backed by the OS keychain (macOS Keychain, Secret Service, ...)
+pub struct KeyringStore;
+
+impl KeyringStore {
+    fn entry(kind: SecretKind, profile: &str) -> Result<Entry> {
+        Entry::new(kind.service_name(), profile).map_err(|e| Error::KeychainError(e.to_string()))
+    }
+}
+
+impl CredentialStore for KeyringStore {
+    fn get(&self, kind: SecretKind, profile: &str) -> Result<String> {
+        Self::entry(kind, profile)?.get_password().map_err(|e| {
+            Error::KeychainError(format!(
+                "Failed to get {} for profile '{}': {}",
+                kind, profile, e
+            ))
+        })
+    }
+
+    fn store(&self, kind: SecretKind, profile: &str, secret: &str) -> Result<()> {
+        Self::entry(kind, profile)?
+            .set_password(secret)
+            .map_err(|e| Error::KeychainError(e.to_string()))
+    }
+
+    fn delete(&self, kind: SecretKind, profile: &str) -> Result<()> {
+        Self::entry(kind, profile)?
+            .delete_password()
+            .map_err(|e| Error::KeychainError(e.to_string()))
+    }
+
+    /// The OS keychain cannot be enumerated, so probe the entries of every
+    /// profile known to config.toml instead.
+    fn list(&self) -> Result<Vec<(SecretKind, String)>> {
+        let config = config::load()?;
+        let mut found = Vec::new();
+
+        for profile in &config.profiles {
+            for kind in SecretKind::ALL {
+                if self.contains(kind, &profile.name)? {
+                    found.push((kind, profile.name.clone()));
+                }
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// keyring has no existence check, so this reads the one entry asked about;
+    /// a missing entry is `false`, a locked or unreachable keychain an error
+    fn contains(&self, kind: SecretKind, profile: &str) -> Result<bool> {
+        match Self::entry(kind, profile)?.get_password() {
+            Ok(_) => Ok(true),
+            Err(keyring::Error::NoEntry) => Ok(false),
+            Err(e) => Err(Error::KeychainError(format!(
+                "Failed to look up {} for profile '{}': {}",
+                kind, profile, e
+            ))),
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use super::{CredentialStore, SecretKind};
+use crate::error::{Error, Result};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+/// In-memory credential store for hermetic tests
+#[derive(Default)]
+pub struct MemoryStore {
+    secrets: RefCell<BTreeMap<(SecretKind, String), String>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CredentialStore for MemoryStore {
+    fn get(&self, kind: SecretKind, profile: &str) -> Result<String> {
+        self.secrets
+            .borrow()
+            .get(&(kind, profile.to_string()))
+            .cloned()
+            .ok_or_else(|| {
+                Error::KeychainError(format!("No {} stored for profile '{}'", kind, profile))
+            })
+    }
+
+    fn store(&self, kind: SecretKind, profile: &str, secret: &str) -> Result<()> {
+        self.secrets
+            .borrow_mut()
+            .insert((kind, profile.to_string()), secret.to_string());
+        Ok(())
+    }
+
+    fn delete(&self, kind: SecretKind, profile: &str) -> Result<()> {
+        self.secrets
+            .borrow_mut()
+            .remove(&(kind, profile.to_string()))
+            .map(|_| ())
+            .ok_or_else(|| {
+                Error::KeychainError(format!("No {} stored for profile '{}'", kind, profile))
+            })
+    }
+
+    fn list(&self) -> Result<Vec<(SecretKind, String)>> {
+        Ok(self.secrets.borrow().keys().cloned().collect())
+    }
+}
@@ -0,0 +1,129 @@
+mod file_store;
+mod keyring_store;
+#[cfg(test)]
+mod memory;
+
+pub use file_store::FileStore;
+pub use keyring_store::KeyringStore;
+#[cfg(test)]
+pub use memory::MemoryStore;
+
+use crate::core::config;
+use crate::error::Result;
+use crate::types::{StorageBackend, StorageConfig};
+use serde::{Deserialize, Serialize};
+
+/// Kind of secret held for a profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SecretKind {
+    ApiKey,
+    OAuthToken,
+    RefreshToken,
+}
+
+impl SecretKind {
+    pub const ALL: [SecretKind; 3] = [
+        SecretKind::ApiKey,
+        SecretKind::OAuthToken,
+        SecretKind::RefreshToken,
+    ];
+
+    /// Keychain service name used for this kind of secret
+    pub fn service_name(&self) -> &'static str {
+        match self {
+            SecretKind::ApiKey => "claude-vault",
+            SecretKind::OAuthToken => "claude-vault-oauth",
+            SecretKind::RefreshToken => "claude-vault-oauth-refresh",
+        }
+    }
+}
+
+impl std::fmt::Display for SecretKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SecretKind::ApiKey => write!(f, "API key"),
+            SecretKind::OAuthToken => write!(f, "OAuth token"),
+            SecretKind::RefreshToken => write!(f, "refresh token"),
+        }
+    }
+}
+
+/// Storage backend for profile secrets
+pub trait CredentialStore {
+    /// Retrieve a secret, failing if it does not exist
+    fn get(&self, kind: SecretKind, profile: &str) -> Result<String>;
+
+    /// Store a secret, overwriting any existing value
+    fn store(&self, kind: SecretKind, profile: &str, secret: &str) -> Result<()>;
+
+    /// Delete a secret, failing if it does not exist
+    fn delete(&self, kind: SecretKind, profile: &str) -> Result<()>;
+
+    /// List the secrets held by this store (never the secret values)
+    fn list(&self) -> Result<Vec<(SecretKind, String)>>;
+
+    /// Whether a secret is stored, without handing its value to the caller
+    fn contains(&self, kind: SecretKind, profile: &str) -> Result<bool> {
+        Ok(self
+            .list()?
+            .iter()
+            .any(|(stored_kind, name)| *stored_kind == kind && name == profile))
+    }
+}
+
+/// Open the credential store selected in config.toml
+pub fn open() -> Result<Box<dyn CredentialStore>> {
+    let config = config::load()?;
+    open_backend(&config.storage)
+}
+
+/// Open a specific credential store backend
+pub fn open_backend(storage: &StorageConfig) -> Result<Box<dyn CredentialStore>> {
+    match storage.backend {
+        StorageBackend::Keyring => Ok(Box::new(KeyringStore)),
+        StorageBackend::File => Ok(Box::new(FileStore::open(storage.kdf)?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_names_are_stable() {
+        // Existing keychain entries are keyed by these names
+        assert_eq!(SecretKind::ApiKey.service_name(), "claude-vault");
+        assert_eq!(SecretKind::OAuthToken.service_name(), "claude-vault-oauth");
+        assert_eq!(
+            SecretKind::RefreshToken.service_name(),
+            "claude-vault-oauth-refresh"
+        );
+    }
+
+    #[test]
+    fn test_memory_store_roundtrip() {
+        let store: Box<dyn CredentialStore> = Box::new(MemoryStore::new());
+
+        store.store(SecretKind::OAuthToken, "work", "token").unwrap();
+        store.store(SecretKind::RefreshToken, "work", "refresh").unwrap();
+
+        assert_eq!(store.get(SecretKind::OAuthToken, "work").unwrap(), "token");
+        assert!(store.get(SecretKind::ApiKey, "work").is_err());
+        assert!(store.contains(SecretKind::OAuthToken, "work").unwrap());
+        assert!(!store.contains(SecretKind::ApiKey, "work").unwrap());
+        assert!(store.contains(SecretKind::RefreshToken, "work").unwrap());
+        assert!(!store.contains(SecretKind::OAuthToken, "personal").unwrap());
+        assert_eq!(
+            store.list().unwrap(),
+            vec![
+                (SecretKind::OAuthToken, "work".to_string()),
+                (SecretKind::RefreshToken, "work".to_string()),
+            ]
+        );
+
+        store.delete(SecretKind::OAuthToken, "work").unwrap();
+        assert!(store.get(SecretKind::OAuthToken, "work").is_err());
+        assert!(store.delete(SecretKind::OAuthToken, "work").is_err());
+    }
+}
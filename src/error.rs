@@ -1,6 +1,8 @@
 use thiserror::Error;
 
 #[derive(Error, Debug)]
+// The `*Error` variants are matched on throughout; renaming them isn't worth the churn
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     #[error("Profile '{0}' not found")]
     ProfileNotFound(String),
@@ -38,11 +40,114 @@ pub enum Error {
     #[error("JSON error: {0}")]
     JsonError(#[from] serde_json::Error),
 
+    #[error("YAML error: {0}")]
+    YamlError(#[from] serde_yaml_ng::Error),
+
     #[error("No profile detected and no default profile set")]
     NoProfileDetected,
 
     #[error("Profile '{0}' in .claude-profile does not exist")]
     InvalidProfileReference(String),
+
+    #[error("Profile '{profile}' does not hold the {required} that .claude-profile requires")]
+    ProfileTypeMismatch {
+        profile: String,
+        required: crate::types::CredentialType,
+    },
+
+    #[error("Failed to refresh token for profile '{profile}': {reason}")]
+    TokenRefreshFailed { profile: String, reason: String },
+
+    #[error("Token request failed: {0}")]
+    TokenRequestFailed(String),
+
+    #[error("Agent error: {0}")]
+    AgentError(String),
+
+    #[error("{0}")]
+    ClaudeCodeBusy(String),
+
+    #[error("Failed to execute '{command}': {source}")]
+    CommandFailed {
+        command: String,
+        source: std::io::Error,
+    },
+}
+
+/// Exit code of `exec` when claude-vault itself fails, so it can't be mistaken
+/// for an exit code of the command (same convention as `env` and `nice`)
+pub const EXEC_FAILURE_EXIT_CODE: i32 = 125;
+
+impl Error {
+    /// Stable identifier of the error kind, reported in structured output
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::ProfileNotFound(_) => "profile_not_found",
+            Error::ProfileAlreadyExists(_) => "profile_already_exists",
+            Error::InvalidProfileName(_) => "invalid_profile_name",
+            Error::EmptyProfileName => "empty_profile_name",
+            Error::ProfileNameTooLong => "profile_name_too_long",
+            Error::InvalidApiKey => "invalid_api_key",
+            Error::KeychainError(_) => "keychain_error",
+            Error::ConfigError(_) => "config_error",
+            Error::IoError(_) => "io_error",
+            Error::TomlError(_) => "toml_error",
+            Error::TomlSerError(_) => "toml_serialization_error",
+            Error::JsonError(_) => "json_error",
+            Error::YamlError(_) => "yaml_error",
+            Error::NoProfileDetected => "no_profile_detected",
+            Error::InvalidProfileReference(_) => "invalid_profile_reference",
+            Error::ProfileTypeMismatch { .. } => "profile_type_mismatch",
+            Error::TokenRefreshFailed { .. } => "token_refresh_failed",
+            Error::TokenRequestFailed(_) => "token_request_failed",
+            Error::AgentError(_) => "agent_error",
+            Error::ClaudeCodeBusy(_) => "claude_code_busy",
+            Error::CommandFailed { .. } => "command_failed",
+        }
+    }
+
+    /// Stable process exit code for the error (see "Exit Codes" in the README)
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::IoError(_)
+            | Error::TomlSerError(_)
+            | Error::JsonError(_)
+            | Error::YamlError(_) => 1,
+            Error::InvalidProfileName(_)
+            | Error::EmptyProfileName
+            | Error::ProfileNameTooLong
+            | Error::InvalidApiKey => 2,
+            Error::ProfileNotFound(_) | Error::InvalidProfileReference(_) => 3,
+            Error::ProfileAlreadyExists(_) => 4,
+            Error::NoProfileDetected => 5,
+            Error::KeychainError(_) => 6,
+            Error::ConfigError(_) | Error::TomlError(_) => 7,
+            Error::TokenRefreshFailed { .. } => 8,
+            Error::TokenRequestFailed(_) => 9,
+            Error::AgentError(_) => 10,
+            Error::ClaudeCodeBusy(_) => 11,
+            Error::ProfileTypeMismatch { .. } => 12,
+            Error::CommandFailed { source, .. } => Self::command_exit_code(source),
+        }
+    }
+
+    /// Exit code of `exec` failing with this error: 126/127 if the command could
+    /// not be run, `EXEC_FAILURE_EXIT_CODE` for any failure of claude-vault itself
+    pub fn exec_exit_code(&self) -> i32 {
+        match self {
+            Error::CommandFailed { source, .. } => Self::command_exit_code(source),
+            _ => EXEC_FAILURE_EXIT_CODE,
+        }
+    }
+
+    /// 127 if the command does not exist, 126 if it could not be executed
+    fn command_exit_code(error: &std::io::Error) -> i32 {
+        if error.kind() == std::io::ErrorKind::NotFound {
+            127
+        } else {
+            126
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -62,4 +167,52 @@ mod tests {
         let err = Error::ProfileAlreadyExists("work".to_string());
         assert_eq!(err.to_string(), "Profile 'work' already exists");
     }
+
+    #[test]
+    fn test_exit_codes_are_stable() {
+        assert_eq!(Error::ProfileNotFound("work".to_string()).exit_code(), 3);
+        assert_eq!(Error::NoProfileDetected.exit_code(), 5);
+        assert_eq!(Error::KeychainError("locked".to_string()).exit_code(), 6);
+        assert_eq!(
+            Error::TokenRefreshFailed {
+                profile: "work".to_string(),
+                reason: "invalid_grant".to_string(),
+            }
+            .exit_code(),
+            8
+        );
+        assert_eq!(Error::ClaudeCodeBusy("busy".to_string()).exit_code(), 11);
+    }
+
+    #[test]
+    fn test_exec_exit_codes() {
+        let not_found = Error::CommandFailed {
+            command: "nope".to_string(),
+            source: std::io::Error::from(std::io::ErrorKind::NotFound),
+        };
+        let denied = Error::CommandFailed {
+            command: "./script".to_string(),
+            source: std::io::Error::from(std::io::ErrorKind::PermissionDenied),
+        };
+
+        assert_eq!(not_found.exec_exit_code(), 127);
+        assert_eq!(denied.exec_exit_code(), 126);
+        assert_eq!(
+            Error::NoProfileDetected.exec_exit_code(),
+            EXEC_FAILURE_EXIT_CODE
+        );
+    }
+
+    #[test]
+    fn test_error_codes_are_stable() {
+        assert_eq!(
+            Error::ProfileNotFound("work".to_string()).code(),
+            "profile_not_found"
+        );
+        assert_eq!(Error::NoProfileDetected.code(), "no_profile_detected");
+        assert_eq!(
+            Error::KeychainError("locked".to_string()).code(),
+            "keychain_error"
+        );
+    }
 }
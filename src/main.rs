@@ -5,8 +5,5 @@ mod types;
 mod utils;
 
 fn main() {
-    if let Err(e) = cli::run() {
-        eprintln!("Error: {}", e);
-        std::process::exit(1);
-    }
+    std::process::exit(cli::run());
 }
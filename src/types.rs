@@ -1,22 +1,17 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum CredentialType {
+    #[default]
     #[serde(rename = "api-key")]
     ApiKey,
     #[serde(rename = "oauth")]
     OAuth,
 }
 
-impl Default for CredentialType {
-    fn default() -> Self {
-        CredentialType::ApiKey
-    }
-}
-
 impl std::fmt::Display for CredentialType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -38,6 +33,24 @@ pub struct Profile {
     pub last_used: Option<DateTime<Utc>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub expires_at: Option<DateTime<Utc>>,
+    /// Per-profile override of `ExpiryPolicy::refresh_ahead_minutes`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_ahead_minutes: Option<i64>,
+    /// Per-profile overrides of the global `[oauth]` settings
+    #[serde(default, skip_serializing_if = "OAuthOverrides::is_empty")]
+    pub oauth: OAuthOverrides,
+    /// Claude subscription of the OAuth account ("pro", "max", ...)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subscription_type: Option<String>,
+    /// Scopes the OAuth token was granted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<String>>,
+    /// Account the OAuth token belongs to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account: Option<AccountIdentity>,
+    /// Labels for grouping profiles, kept sorted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
     #[serde(default)]
     pub metadata: HashMap<String, String>,
 }
@@ -51,6 +64,12 @@ impl Profile {
             created_at: Utc::now(),
             last_used: None,
             expires_at: None,
+            refresh_ahead_minutes: None,
+            oauth: OAuthOverrides::default(),
+            subscription_type: None,
+            scopes: None,
+            account: None,
+            tags: Vec::new(),
             metadata: HashMap::new(),
         }
     }
@@ -67,10 +86,23 @@ impl Profile {
             created_at: Utc::now(),
             last_used: None,
             expires_at: None,
+            refresh_ahead_minutes: None,
+            oauth: OAuthOverrides::default(),
+            subscription_type: None,
+            scopes: None,
+            account: None,
+            tags: Vec::new(),
             metadata: HashMap::new(),
         }
     }
 
+    /// Record what is known about the OAuth login the profile's tokens came from
+    pub fn set_oauth_identity(&mut self, identity: OAuthIdentity) {
+        self.subscription_type = identity.subscription_type;
+        self.scopes = identity.scopes;
+        self.account = identity.account.filter(|account| !account.is_empty());
+    }
+
     pub fn touch(&mut self) {
         self.last_used = Some(Utc::now());
     }
@@ -83,30 +115,567 @@ impl Profile {
         }
     }
 
-    pub fn expires_soon(&self) -> bool {
-        if let Some(expires_at) = self.expires_at {
-            let now = Utc::now();
-            let time_until_expiry = expires_at - now;
-            time_until_expiry.num_hours() < 24 && time_until_expiry.num_hours() >= 0
-        } else {
-            false
+    /// Time left until expiry (negative once expired)
+    pub fn remaining_validity(&self) -> Option<Duration> {
+        self.expires_at.map(|expires_at| expires_at - Utc::now())
+    }
+
+    /// Whether the credential expires within `window` (or already has)
+    pub fn expires_within(&self, window: Duration) -> bool {
+        self.remaining_validity()
+            .map(|remaining| remaining < window)
+            .unwrap_or(false)
+    }
+
+    /// How long before expiry this profile's token should be refreshed
+    pub fn refresh_window(&self, policy: &ExpiryPolicy) -> Duration {
+        Duration::minutes(
+            self.refresh_ahead_minutes
+                .unwrap_or(policy.refresh_ahead_minutes),
+        )
+    }
+
+    /// Whether the token should be refreshed before being handed out
+    pub fn needs_refresh(&self, policy: &ExpiryPolicy) -> bool {
+        self.expires_within(self.refresh_window(policy))
+    }
+
+    /// Whether the credential is still valid but within the warning window
+    pub fn expires_soon(&self, policy: &ExpiryPolicy) -> bool {
+        !self.is_expired() && self.expires_within(Duration::hours(policy.warn_ahead_hours))
+    }
+}
+
+/// Settings of a project, read from its `.claude-profile` file
+///
+/// The file holds either just a profile name or a TOML table with `profile`
+/// and these optional project-level settings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectProfile {
+    pub profile: String,
+    /// Profiles to use, in order, if `profile` is missing or unsuitable
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallbacks: Vec<String>,
+    /// Credential type the selected profile must have
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub required_type: Option<CredentialType>,
+    /// Default model, exported as `ANTHROPIC_MODEL`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// API base URL, exported as `ANTHROPIC_BASE_URL`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+    /// Extra environment variables for commands run in the project
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+}
+
+impl ProjectProfile {
+    pub fn new(profile: String) -> Self {
+        Self {
+            profile,
+            ..Self::default()
+        }
+    }
+
+    /// `profile` followed by the fallbacks
+    pub fn candidates(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.profile).chain(&self.fallbacks)
+    }
+
+    /// Environment variables the project sets; `model` and `base_url` take
+    /// precedence over the same variables in `env`.
+    ///
+    /// A project file comes with the repository, so variables that decide
+    /// where credentials are sent (the base URL and proxies) are only accepted
+    /// with a value in `allowed_upstreams`, and other `ANTHROPIC_*` variables
+    /// not at all.
+    pub fn env_vars(
+        &self,
+        allowed_upstreams: &[String],
+    ) -> crate::error::Result<BTreeMap<String, String>> {
+        let mut vars = self.env.clone();
+        if let Some(ref model) = self.model {
+            vars.insert("ANTHROPIC_MODEL".to_string(), model.clone());
+        }
+        if let Some(ref base_url) = self.base_url {
+            vars.insert("ANTHROPIC_BASE_URL".to_string(), base_url.clone());
+        }
+
+        let allowed = |value: &str| {
+            allowed_upstreams
+                .iter()
+                .any(|url| url.trim_end_matches('/') == value.trim_end_matches('/'))
+        };
+        for (name, value) in &vars {
+            let upper = name.to_ascii_uppercase();
+            if ROUTING_ENV_VARS.contains(&upper.as_str()) {
+                if !allowed(value) {
+                    return Err(crate::error::Error::ConfigError(format!(
+                        ".claude-profile sets {} to '{}'; add it to [proxy] allowed_upstreams \
+                         in config.toml to send credentials there",
+                        name, value
+                    )));
+                }
+            } else if upper.starts_with("ANTHROPIC_") && upper != "ANTHROPIC_MODEL" {
+                return Err(crate::error::Error::ConfigError(format!(
+                    ".claude-profile may not set {}",
+                    name
+                )));
+            }
+        }
+
+        Ok(vars)
+    }
+}
+
+/// Variables that decide where API requests, and with them credentials, go
+const ROUTING_ENV_VARS: &[&str] = &[
+    "ANTHROPIC_BASE_URL",
+    "HTTPS_PROXY",
+    "HTTP_PROXY",
+    "ALL_PROXY",
+];
+
+/// Changes to a profile's descriptive fields, as made by `claude-vault edit`
+#[derive(Debug, Clone, Default)]
+pub struct ProfileEdit {
+    /// New description; an empty string removes it
+    pub description: Option<String>,
+    /// Metadata entries to add or overwrite
+    pub set: Vec<(String, String)>,
+    /// Metadata keys to remove
+    pub unset: Vec<String>,
+    /// Tags to add
+    pub tags: Vec<String>,
+    /// Tags to remove
+    pub untags: Vec<String>,
+}
+
+impl ProfileEdit {
+    pub fn is_empty(&self) -> bool {
+        self.description.is_none()
+            && self.set.is_empty()
+            && self.unset.is_empty()
+            && self.tags.is_empty()
+            && self.untags.is_empty()
+    }
+
+    /// Apply the edit; removals happen before additions
+    pub fn apply(&self, profile: &mut Profile) {
+        if let Some(ref description) = self.description {
+            profile.description = Some(description.clone()).filter(|d| !d.is_empty());
+        }
+
+        for key in &self.unset {
+            profile.metadata.remove(key);
+        }
+        for (key, value) in &self.set {
+            profile.metadata.insert(key.clone(), value.clone());
         }
+
+        profile.tags.retain(|tag| !self.untags.contains(tag));
+        for tag in &self.tags {
+            if !profile.tags.contains(tag) {
+                profile.tags.push(tag.clone());
+            }
+        }
+        profile.tags.sort();
     }
 }
 
+/// Selects profiles by tag and metadata, as in `claude-vault list --tag`
+#[derive(Debug, Clone, Default)]
+pub struct ProfileFilter {
+    /// Tags a profile must all carry
+    pub tags: Vec<String>,
+    /// Metadata keys a profile must have, with the given value if one is set
+    pub metadata: Vec<(String, Option<String>)>,
+}
+
+impl ProfileFilter {
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.metadata.is_empty()
+    }
+
+    pub fn matches(&self, profile: &Profile) -> bool {
+        self.tags.iter().all(|tag| profile.tags.contains(tag))
+            && self
+                .metadata
+                .iter()
+                .all(|(key, value)| match (profile.metadata.get(key), value) {
+                    (Some(actual), Some(expected)) => actual == expected,
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                })
+    }
+}
+
+/// Credential storage backend selected in config.toml
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// OS keychain (macOS Keychain, Secret Service, Windows Credential Manager)
+    #[default]
+    Keyring,
+    /// Passphrase-encrypted file in the vault directory
+    File,
+}
+
+/// Anthropic account an OAuth token was issued for
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AccountIdentity {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub organization_uuid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub organization_name: Option<String>,
+}
+
+impl AccountIdentity {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// What is known about an OAuth login beyond its tokens, as reported by
+/// Claude Code or the token endpoint
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OAuthIdentity {
+    pub subscription_type: Option<String>,
+    pub scopes: Option<Vec<String>>,
+    pub account: Option<AccountIdentity>,
+}
+
+/// When OAuth tokens are refreshed ahead of expiry and when expiry is warned about
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ExpiryPolicy {
+    /// Refresh tokens that expire within this many minutes
+    pub refresh_ahead_minutes: i64,
+    /// Warn about credentials that expire within this many hours
+    pub warn_ahead_hours: i64,
+}
+
+impl ExpiryPolicy {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for ExpiryPolicy {
+    fn default() -> Self {
+        Self {
+            refresh_ahead_minutes: 5,
+            warn_ahead_hours: 24,
+        }
+    }
+}
+
+/// Which profiles `switch` has made Claude Code use
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ClaudeCodeState {
+    /// Profile Claude Code is currently logged in as
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active_profile: Option<String>,
+    /// Profile Claude Code used before the last switch, for `switch --back`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_profile: Option<String>,
+}
+
+impl ClaudeCodeState {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Settings for `exec --isolated`, which gives each profile its own Claude Code config directory
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct IsolationConfig {
+    /// Entries of the main Claude Code config directory (e.g. "settings.json",
+    /// "commands") symlinked into every profile's directory
+    pub shared: Vec<String>,
+}
+
+impl IsolationConfig {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// `[[rules]]` entry mapping directories to a profile
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Rule {
+    #[serde(flatten)]
+    pub target: RuleTarget,
+    pub profile: String,
+}
+
+/// What a rule matches, each a glob that also covers everything below a match
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleTarget {
+    /// Directory such as "~/work/**" or "~/oss/*"
+    Path(String),
+    /// Git remote as host/path, such as "github.com/acme" or "github.com/acme/*"
+    Remote(String),
+}
+
+impl RuleTarget {
+    pub fn pattern(&self) -> &str {
+        match self {
+            RuleTarget::Path(pattern) | RuleTarget::Remote(pattern) => pattern,
+        }
+    }
+}
+
+impl std::fmt::Display for RuleTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuleTarget::Path(pattern) => write!(f, "path {}", pattern),
+            RuleTarget::Remote(pattern) => write!(f, "remote {}", pattern),
+        }
+    }
+}
+
+/// Argon2id cost parameters used when writing the encrypted credential file
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct KdfConfig {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl KdfConfig {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for KdfConfig {
+    fn default() -> Self {
+        Self {
+            memory_kib: 64 * 1024,
+            iterations: 3,
+            parallelism: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct StorageConfig {
+    #[serde(default)]
+    pub backend: StorageBackend,
+    #[serde(default, skip_serializing_if = "KdfConfig::is_default")]
+    pub kdf: KdfConfig,
+}
+
+/// Settings for the background credential agent
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct AgentConfig {
+    /// Seconds a cached secret may go unused before the agent forgets it
+    pub idle_ttl_seconds: u64,
+}
+
+impl AgentConfig {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        Self {
+            idle_ttl_seconds: 15 * 60,
+        }
+    }
+}
+
+/// Settings for the credential-injecting API proxy
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ProxyConfig {
+    /// Base URL requests are forwarded to
+    pub upstream: String,
+    /// URLs a `.claude-profile` may use as base URL or proxy
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allowed_upstreams: Vec<String>,
+}
+
+impl ProxyConfig {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            upstream: "https://api.anthropic.com".to_string(),
+            allowed_upstreams: Vec::new(),
+        }
+    }
+}
+
+/// OAuth client settings used by `login` and token refresh
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct OAuthConfig {
+    pub authorize_endpoint: String,
+    pub token_endpoint: String,
+    pub client_id: String,
+    pub scopes: Vec<String>,
+    /// Extra parameters added to every token request
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra_params: BTreeMap<String, String>,
+}
+
+impl OAuthConfig {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Settings with a profile's overrides applied on top
+    pub fn with_overrides(&self, overrides: &OAuthOverrides) -> OAuthConfig {
+        let mut merged = self.clone();
+        if let Some(ref endpoint) = overrides.authorize_endpoint {
+            merged.authorize_endpoint = endpoint.clone();
+        }
+        if let Some(ref endpoint) = overrides.token_endpoint {
+            merged.token_endpoint = endpoint.clone();
+        }
+        if let Some(ref client_id) = overrides.client_id {
+            merged.client_id = client_id.clone();
+        }
+        if let Some(ref scopes) = overrides.scopes {
+            merged.scopes = scopes.clone();
+        }
+        merged.extra_params.extend(
+            overrides
+                .extra_params
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone())),
+        );
+        merged
+    }
+}
+
+/// Per-profile OAuth settings; unset fields fall back to the global `[oauth]` section
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OAuthOverrides {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authorize_endpoint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_endpoint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra_params: BTreeMap<String, String>,
+}
+
+impl OAuthOverrides {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// These overrides with `other`'s set fields taking precedence
+    pub fn merged_with(&self, other: &OAuthOverrides) -> OAuthOverrides {
+        let mut extra_params = self.extra_params.clone();
+        extra_params.extend(
+            other
+                .extra_params
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone())),
+        );
+
+        OAuthOverrides {
+            authorize_endpoint: other
+                .authorize_endpoint
+                .clone()
+                .or_else(|| self.authorize_endpoint.clone()),
+            token_endpoint: other
+                .token_endpoint
+                .clone()
+                .or_else(|| self.token_endpoint.clone()),
+            client_id: other.client_id.clone().or_else(|| self.client_id.clone()),
+            scopes: other.scopes.clone().or_else(|| self.scopes.clone()),
+            extra_params,
+        }
+    }
+}
+
+impl Default for OAuthConfig {
+    fn default() -> Self {
+        Self {
+            authorize_endpoint: "https://claude.ai/oauth/authorize".to_string(),
+            token_endpoint: "https://api.anthropic.com/v1/oauth/token".to_string(),
+            client_id: "9d1c250a-e61b-44d9-88ed-5944d1962f5e".to_string(),
+            scopes: vec![
+                "user:inference".to_string(),
+                "user:profile".to_string(),
+                "user:sessions:claude_code".to_string(),
+            ],
+            extra_params: BTreeMap::new(),
+        }
+    }
+}
+
+/// Version written to new config files; older files are migrated on load
+/// (see `core::migrate`)
+pub const CONFIG_VERSION: &str = "1.2";
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     pub version: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub default_profile: Option<String>,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default, skip_serializing_if = "AgentConfig::is_default")]
+    pub agent: AgentConfig,
+    #[serde(default, skip_serializing_if = "ProxyConfig::is_default")]
+    pub proxy: ProxyConfig,
+    #[serde(default, skip_serializing_if = "OAuthConfig::is_default")]
+    pub oauth: OAuthConfig,
+    #[serde(default, skip_serializing_if = "ExpiryPolicy::is_default")]
+    pub expiry: ExpiryPolicy,
+    #[serde(default, skip_serializing_if = "IsolationConfig::is_default")]
+    pub isolation: IsolationConfig,
+    #[serde(default, skip_serializing_if = "ClaudeCodeState::is_default")]
+    pub claude_code: ClaudeCodeState,
+    /// Detection rules, checked before `.claude-profile` files
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
     pub profiles: Vec<Profile>,
 }
 
 impl Config {
+    /// OAuth settings for a profile: the global `[oauth]` section plus its overrides
+    pub fn oauth_for(&self, profile: &Profile) -> OAuthConfig {
+        self.oauth.with_overrides(&profile.oauth)
+    }
+
     pub fn new() -> Self {
         Self {
-            version: "1.0".to_string(),
+            version: CONFIG_VERSION.to_string(),
             default_profile: None,
+            storage: StorageConfig::default(),
+            agent: AgentConfig::default(),
+            proxy: ProxyConfig::default(),
+            oauth: OAuthConfig::default(),
+            expiry: ExpiryPolicy::default(),
+            isolation: IsolationConfig::default(),
+            claude_code: ClaudeCodeState::default(),
+            rules: Vec::new(),
             profiles: Vec::new(),
         }
     }
@@ -145,6 +714,45 @@ impl Config {
             self.default_profile = None;
         }
 
+        // Forget the profile as a Claude Code switch target
+        if self.claude_code.active_profile.as_deref() == Some(name) {
+            self.claude_code.active_profile = None;
+        }
+        if self.claude_code.previous_profile.as_deref() == Some(name) {
+            self.claude_code.previous_profile = None;
+        }
+
+        // Rules would only point detection at a missing profile
+        self.rules.retain(|rule| rule.profile != name);
+
+        Ok(())
+    }
+
+    /// Rename a profile, updating every reference to it
+    pub fn rename_profile(&mut self, old: &str, new: &str) -> crate::error::Result<()> {
+        if self.profile_exists(new) {
+            return Err(crate::error::Error::ProfileAlreadyExists(new.to_string()));
+        }
+
+        let profile = self
+            .find_profile_mut(old)
+            .ok_or_else(|| crate::error::Error::ProfileNotFound(old.to_string()))?;
+        profile.name = new.to_string();
+
+        for reference in [
+            &mut self.default_profile,
+            &mut self.claude_code.active_profile,
+            &mut self.claude_code.previous_profile,
+        ] {
+            if reference.as_deref() == Some(old) {
+                *reference = Some(new.to_string());
+            }
+        }
+
+        for rule in self.rules.iter_mut().filter(|rule| rule.profile == old) {
+            rule.profile = new.to_string();
+        }
+
         Ok(())
     }
 }
@@ -175,6 +783,194 @@ mod tests {
         assert!(profile.last_used.is_some());
     }
 
+    #[test]
+    fn test_profile_refresh_window() {
+        let policy = ExpiryPolicy::default();
+        let mut profile = Profile::new("test".to_string(), None);
+        assert!(!profile.needs_refresh(&policy));
+
+        profile.expires_at = Some(Utc::now() + Duration::minutes(3));
+        assert!(!profile.is_expired());
+        assert!(profile.needs_refresh(&policy));
+        assert!(profile.expires_soon(&policy));
+
+        profile.expires_at = Some(Utc::now() + Duration::minutes(30));
+        assert!(!profile.needs_refresh(&policy));
+
+        profile.refresh_ahead_minutes = Some(60);
+        assert!(profile.needs_refresh(&policy));
+    }
+
+    #[test]
+    fn test_profile_expires_soon_uses_policy() {
+        let mut profile = Profile::new("test".to_string(), None);
+        profile.expires_at = Some(Utc::now() + Duration::hours(30));
+
+        assert!(!profile.expires_soon(&ExpiryPolicy::default()));
+
+        let policy = ExpiryPolicy {
+            warn_ahead_hours: 48,
+            ..ExpiryPolicy::default()
+        };
+        assert!(profile.expires_soon(&policy));
+
+        profile.expires_at = Some(Utc::now() - Duration::hours(1));
+        assert!(!profile.expires_soon(&policy));
+    }
+
+    #[test]
+    fn test_oauth_overrides() {
+        let mut global = OAuthConfig::default();
+        global
+            .extra_params
+            .insert("audience".to_string(), "api".to_string());
+
+        let overrides = OAuthOverrides {
+            token_endpoint: Some("http://127.0.0.1:8080/token".to_string()),
+            extra_params: BTreeMap::from([("tenant".to_string(), "acme".to_string())]),
+            ..OAuthOverrides::default()
+        };
+
+        let merged = global.with_overrides(&overrides);
+        assert_eq!(merged.token_endpoint, "http://127.0.0.1:8080/token");
+        assert_eq!(merged.client_id, global.client_id);
+        assert_eq!(merged.extra_params.len(), 2);
+
+        let cli = OAuthOverrides {
+            client_id: Some("other".to_string()),
+            ..OAuthOverrides::default()
+        };
+        let combined = overrides.merged_with(&cli);
+        assert_eq!(combined.client_id.as_deref(), Some("other"));
+        assert_eq!(combined.token_endpoint, overrides.token_endpoint);
+        assert!(OAuthOverrides::default().is_empty());
+    }
+
+    #[test]
+    fn test_project_profile() {
+        let project: ProjectProfile = toml::from_str(
+            r#"
+profile = "work"
+fallbacks = ["personal"]
+required_type = "oauth"
+model = "claude-sonnet-4-5"
+base_url = "https://gateway.example.com"
+
+[env]
+ANTHROPIC_MODEL = "overridden"
+HTTPS_PROXY = "http://proxy:3128"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(project.required_type, Some(CredentialType::OAuth));
+        assert_eq!(
+            project.candidates().collect::<Vec<_>>(),
+            vec!["work", "personal"]
+        );
+
+        let allowed = vec![
+            "https://gateway.example.com".to_string(),
+            "http://proxy:3128".to_string(),
+        ];
+        let vars = project.env_vars(&allowed).unwrap();
+        assert_eq!(vars["ANTHROPIC_MODEL"], "claude-sonnet-4-5");
+        assert_eq!(vars["ANTHROPIC_BASE_URL"], "https://gateway.example.com");
+        assert_eq!(vars["HTTPS_PROXY"], "http://proxy:3128");
+
+        assert!(toml::from_str::<ProjectProfile>("profile = \"work\"\nmodle = \"x\"").is_err());
+        assert!(ProjectProfile::new("work".to_string())
+            .env_vars(&[])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_project_profile_env_vars() {
+        let mut project = ProjectProfile::new("work".to_string());
+        project.model = Some("claude-sonnet-4-5".to_string());
+        project
+            .env
+            .insert("NODE_ENV".to_string(), "test".to_string());
+        let vars = project.env_vars(&[]).unwrap();
+        assert_eq!(vars["ANTHROPIC_MODEL"], "claude-sonnet-4-5");
+        assert_eq!(vars["NODE_ENV"], "test");
+
+        // Routing variables need an allow-listed value
+        let allowed = vec!["https://gateway.example.com/".to_string()];
+        project.base_url = Some("https://gateway.example.com".to_string());
+        assert!(project.env_vars(&[]).is_err());
+        assert_eq!(
+            project.env_vars(&allowed).unwrap()["ANTHROPIC_BASE_URL"],
+            "https://gateway.example.com"
+        );
+
+        project.base_url = None;
+        project
+            .env
+            .insert("https_proxy".to_string(), "http://evil:3128".to_string());
+        assert!(project.env_vars(&allowed).is_err());
+
+        project.env.remove("https_proxy");
+        project
+            .env
+            .insert("ANTHROPIC_CUSTOM_HEADERS".to_string(), "x".to_string());
+        assert!(project.env_vars(&allowed).is_err());
+    }
+
+    #[test]
+    fn test_profile_edit() {
+        let mut profile = Profile::new("work".to_string(), Some("Work".to_string()));
+        profile.tags = vec!["old".to_string()];
+        profile
+            .metadata
+            .insert("env".to_string(), "dev".to_string());
+
+        let edit = ProfileEdit {
+            description: Some(String::new()),
+            set: vec![
+                ("team".to_string(), "infra".to_string()),
+                ("env".to_string(), "prod".to_string()),
+            ],
+            unset: vec!["env".to_string()],
+            tags: vec!["prod".to_string(), "infra".to_string(), "prod".to_string()],
+            untags: vec!["old".to_string()],
+        };
+        assert!(!edit.is_empty());
+        assert!(ProfileEdit::default().is_empty());
+
+        edit.apply(&mut profile);
+        assert!(profile.description.is_none());
+        assert_eq!(profile.metadata["team"], "infra");
+        assert_eq!(profile.metadata["env"], "prod");
+        assert_eq!(profile.tags, vec!["infra".to_string(), "prod".to_string()]);
+    }
+
+    #[test]
+    fn test_profile_filter() {
+        let mut profile = Profile::new("work".to_string(), None);
+        profile.tags = vec!["infra".to_string(), "prod".to_string()];
+        profile
+            .metadata
+            .insert("team".to_string(), "infra".to_string());
+
+        let filter = |tags: &[&str], metadata: &[(&str, Option<&str>)]| ProfileFilter {
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            metadata: metadata
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.map(str::to_string)))
+                .collect(),
+        };
+
+        assert!(filter(&[], &[]).matches(&profile));
+        assert!(filter(&["prod"], &[]).matches(&profile));
+        assert!(!filter(&["prod", "dev"], &[]).matches(&profile));
+        assert!(filter(&[], &[("team", None)]).matches(&profile));
+        assert!(filter(&["infra"], &[("team", Some("infra"))]).matches(&profile));
+        assert!(!filter(&[], &[("team", Some("web"))]).matches(&profile));
+        assert!(!filter(&[], &[("env", None)]).matches(&profile));
+    }
+
     #[test]
     fn test_config_add_profile() {
         let mut config = Config::new();
@@ -214,4 +1010,104 @@ mod tests {
         config.remove_profile("test").unwrap();
         assert!(config.default_profile.is_none());
     }
+
+    #[test]
+    fn test_config_remove_switched_profile() {
+        let mut config = Config::new();
+        config
+            .add_profile(Profile::new("work".to_string(), None))
+            .unwrap();
+        config
+            .add_profile(Profile::new("personal".to_string(), None))
+            .unwrap();
+        config.claude_code = ClaudeCodeState {
+            active_profile: Some("work".to_string()),
+            previous_profile: Some("personal".to_string()),
+        };
+
+        config.remove_profile("personal").unwrap();
+        assert_eq!(config.claude_code.active_profile.as_deref(), Some("work"));
+        assert!(config.claude_code.previous_profile.is_none());
+
+        config.remove_profile("work").unwrap();
+        assert!(config.claude_code.is_default());
+    }
+
+    #[test]
+    fn test_config_rules_follow_profiles() {
+        let mut config = Config::new();
+        config
+            .add_profile(Profile::new("work".to_string(), None))
+            .unwrap();
+        config
+            .add_profile(Profile::new("personal".to_string(), None))
+            .unwrap();
+        config.rules = vec![
+            Rule {
+                target: RuleTarget::Path("~/work/**".to_string()),
+                profile: "work".to_string(),
+            },
+            Rule {
+                target: RuleTarget::Remote("github.com/me".to_string()),
+                profile: "personal".to_string(),
+            },
+        ];
+
+        config.rename_profile("work", "acme").unwrap();
+        assert_eq!(config.rules[0].profile, "acme");
+
+        config.remove_profile("personal").unwrap();
+        assert_eq!(config.rules.len(), 1);
+    }
+
+    #[test]
+    fn test_rule_toml_format() {
+        let mut config = Config::new();
+        config.rules = vec![
+            Rule {
+                target: RuleTarget::Path("~/work/**".to_string()),
+                profile: "work".to_string(),
+            },
+            Rule {
+                target: RuleTarget::Remote("github.com/acme".to_string()),
+                profile: "work".to_string(),
+            },
+        ];
+
+        let toml = toml::to_string(&config).unwrap();
+        assert!(toml.contains("[[rules]]\npath = \"~/work/**\"\nprofile = \"work\""));
+        assert!(toml.contains("[[rules]]\nremote = \"github.com/acme\"\nprofile = \"work\""));
+
+        let loaded: Config = toml::from_str(&toml).unwrap();
+        assert_eq!(loaded.rules, config.rules);
+    }
+
+    #[test]
+    fn test_config_rename_profile() {
+        let mut config = Config::new();
+        config
+            .add_profile(Profile::new("work".to_string(), None))
+            .unwrap();
+        config
+            .add_profile(Profile::new("personal".to_string(), None))
+            .unwrap();
+        config.default_profile = Some("work".to_string());
+        config.claude_code.active_profile = Some("work".to_string());
+
+        assert!(matches!(
+            config.rename_profile("work", "personal"),
+            Err(crate::error::Error::ProfileAlreadyExists(_))
+        ));
+        assert!(matches!(
+            config.rename_profile("missing", "other"),
+            Err(crate::error::Error::ProfileNotFound(_))
+        ));
+
+        config.rename_profile("work", "acme").unwrap();
+        assert!(config.profile_exists("acme"));
+        assert!(!config.profile_exists("work"));
+        assert_eq!(config.default_profile.as_deref(), Some("acme"));
+        assert_eq!(config.claude_code.active_profile.as_deref(), Some("acme"));
+        assert!(config.claude_code.previous_profile.is_none());
+    }
 }
